@@ -9,7 +9,6 @@ extern crate structopt_derive;
 extern crate structopt;
 extern crate clap;
 extern crate icecc;
-extern crate libc;
 
 use structopt::StructOpt;
 
@@ -21,62 +20,58 @@ struct Options {
 }
 
 
-fn discover_scheduler(netname: Option<String>) -> Result<icecc::MessageChannel, &'static str>
+fn discover_scheduler(netname: Option<&str>) -> Result<icecc::MessageChannel, String>
 {
-    let mut disco = icecc::ScheduleDiscoverer::new(netname.as_ref());
-    loop {
-        match disco.try_get_scheduler() {
-            Some(chan) =>
-                return Ok(chan),
-            None => {
-                if disco.timed_out() {
-                    return Err("Timed out searching for scheduler");
-                }
-                std::thread::sleep(std::time::Duration::from_millis(50));
-            }
-        }
+    match icecc::discover_blocking(netname, std::time::Duration::from_secs(2)) {
+        Ok(chan) => Ok(chan),
+        Err(icecc::Error::Timeout) => Err("Timed out searching for scheduler".to_string()),
+        Err(e) => Err(e.to_string()),
     }
 }
 
 
 fn main() {
     let opts = Options::from_args();
-    let mut chan = match discover_scheduler(opts.netname) {
-        Ok(chan) => chan,
+    loop {
+        let chan = match discover_scheduler(opts.netname.as_ref().map(String::as_str)) {
+            Ok(chan) => chan,
+            Err(ref e) => {
+                println!("Error: {}", e);
+                ::std::process::exit(1);
+            }
+        };
+        monitor(chan);
+        println!("Scheduler connection closed, searching again...");
+    }
+}
+
+
+fn monitor(chan: icecc::MessageChannel) {
+    let session = match chan.login_as_monitor() {
+        Ok(session) => session,
         Err(ref e) => {
             println!("Error: {}", e);
-            ::std::process::exit(1);
+            return;
         }
     };
 
-    chan.bulk_transfer();
-    chan.send(icecc::Message::from(icecc::msg::Ping::new()));
-
-    while !chan.eof() {
-        let mut pfd = libc::pollfd {
-            fd: chan.fd(),
-            events: libc::POLLIN,
-            revents: 0
-        };
-
-        let ret = unsafe {
-            libc::poll((&mut [pfd]).as_mut_ptr(), 1, -1)
-        };
-
-        while !chan.read_a_bit() || chan.has_message() {
-            match chan.recv(None) {
-                Some(icecc::Message::MonitorStats(ref stats)) =>
-                    handle_monitor_stats(stats),
-                Some(icecc::Message::MonitorLocalJobBegin(ref job)) =>
-                    handle_monitor_local_job_begin(job),
-                Some(icecc::Message::MonitorJobDone(ref job)) =>
-                    handle_monitor_job_done(job),
-                Some(ref msg) =>
-                    println!("Unhandled: {:?}", msg),
-                None => (),  // TODO: Handle re-checking scheduler.
-            }
+    for event in session {
+        match event {
+            Ok(icecc::MonitorEvent::Stats(ref stats)) =>
+                handle_monitor_stats(stats),
+            Ok(icecc::MonitorEvent::GetCS(ref job)) =>
+                handle_monitor_get_cs(job),
+            Ok(icecc::MonitorEvent::JobBegin(ref job)) =>
+                handle_monitor_job_begin(job),
+            Ok(icecc::MonitorEvent::LocalJobBegin(ref job)) =>
+                handle_monitor_local_job_begin(job),
+            Ok(icecc::MonitorEvent::JobDone(ref job)) =>
+                handle_monitor_job_done(job),
+            Ok(ref event) =>
+                println!("Unhandled: {:?}", event),
+            Err(ref e) =>
+                println!("Warning: {}", e),
         }
-        std::thread::sleep(std::time::Duration::from_millis(50));
     }
 }
 
@@ -85,6 +80,15 @@ fn handle_monitor_stats(msg: &icecc::msg::MonitorStats) {
     println!("stats {}: {}", msg.host_id(), msg.message());
 }
 
+fn handle_monitor_get_cs(msg: &icecc::msg::MonitorGetCS) {
+    println!("request {} from host {}: {} ({:?})", msg.job_id(), msg.client_id(),
+             msg.filename(), msg.language());
+}
+
+fn handle_monitor_job_begin(msg: &icecc::msg::MonitorJobBegin) {
+    println!("begin {} on host {} at {}", msg.job_id(), msg.host_id(), msg.start_time());
+}
+
 fn handle_monitor_local_job_begin(msg: &icecc::msg::MonitorLocalJobBegin) {
     println!("begin {} (local): {}", msg.job_id(), msg.filename());
 }
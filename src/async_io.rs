@@ -0,0 +1,316 @@
+//
+// async_io.rs
+// Copyright (C) 2017 Adrian Perez <aperez@igalia.com>
+// Distributed under terms of the MIT license.
+//
+
+use std::future::Future;
+use std::io;
+use std::os::unix::io::{ AsRawFd, RawFd };
+use std::pin::Pin;
+use std::task::{ Context, Poll };
+use std::time::Duration;
+
+use futures_core::Stream;
+use libc;
+use tokio::io::Interest;
+use tokio::io::unix::AsyncFd;
+use tokio::time::{ self, Sleep };
+
+use super::{ DISCOVER_POLL_INTERVAL_MS, Error, Message, MessageChannel, Result, ScheduleDiscoverer };
+use super::timeout_secs;
+
+
+// Checks whether reading from the socket would not block, by peeking at
+// it. Used to decide whether the reactor readiness may be cleared, because
+// read_a_bit() may leave unread data in the socket buffer.
+fn fd_readable(fd: libc::c_int) -> bool {
+    let mut byte = 0u8;
+    let n = unsafe {
+        libc::recv(fd, &mut byte as *mut u8 as *mut libc::c_void, 1, libc::MSG_PEEK | libc::MSG_DONTWAIT)
+    };
+    // Errors other than EAGAIN are left for the next read to report.
+    n >= 0 || io::Error::last_os_error().kind() != io::ErrorKind::WouldBlock
+}
+
+
+pub struct AsyncMessageChannel {
+    inner: AsyncFd<MessageChannel>,
+    failed: bool,
+}
+
+impl AsyncMessageChannel
+{
+    // Must be called from within a Tokio runtime.
+    pub fn new(chan: MessageChannel) -> io::Result<Self> {
+        Ok(Self { inner: AsyncFd::new(chan)?, failed: false })
+    }
+
+    pub fn get_ref(&self) -> &MessageChannel {
+        self.inner.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut MessageChannel {
+        self.inner.get_mut()
+    }
+
+    pub fn into_inner(self) -> MessageChannel {
+        self.inner.into_inner()
+    }
+
+    pub fn send<'a, M: AsRef<Message> + Unpin>(&'a mut self, message: M) -> SendMessage<'a, M> {
+        SendMessage { chan: self, message: Some(message) }
+    }
+}
+
+impl Stream for AsyncMessageChannel
+{
+    type Item = Result<Message>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if this.failed {
+            return Poll::Ready(None);
+        }
+        loop {
+            {
+                let chan = this.inner.get_mut();
+                if chan.has_message() {
+                    return Poll::Ready(Some(chan.try_recv(None)));
+                }
+                if chan.eof() {
+                    return Poll::Ready(None);
+                }
+            }
+
+            let mut guard = match this.inner.poll_read_ready_mut(cx) {
+                Poll::Ready(Ok(guard)) => guard,
+                Poll::Ready(Err(e)) => {
+                    this.failed = true;
+                    return Poll::Ready(Some(Err(Error::Io(e))));
+                },
+                Poll::Pending => return Poll::Pending,
+            };
+            if !guard.get_inner_mut().read_a_bit() {
+                this.failed = true;
+                return Poll::Ready(Some(Err(Error::Protocol)));
+            }
+            if !fd_readable(guard.get_inner().fd()) {
+                guard.clear_ready();
+            }
+        }
+    }
+}
+
+
+pub struct SendMessage<'a, M> {
+    chan: &'a mut AsyncMessageChannel,
+    message: Option<M>,
+}
+
+// Makes as much progress as possible without blocking: the handshake has
+// to be completed before the message can be encoded, and then written out.
+fn send_some<M: AsRef<Message>>(chan: &mut MessageChannel, message: &mut Option<M>) -> Result<bool> {
+    if message.is_some() {
+        if !chan.poll_handshake()? {
+            return Ok(false);
+        }
+        chan.start_send(message.take().unwrap())?;
+    }
+    chan.poll_flush()
+}
+
+impl<'a, M: AsRef<Message> + Unpin> Future for SendMessage<'a, M>
+{
+    type Output = Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        loop {
+            // Pending output always goes first. Only an incomplete handshake
+            // waits for the peer: once it is done, the socket may have been
+            // drained already, and the message can be written right away.
+            let handshaking = match this.message {
+                Some(_) => match this.chan.get_mut().poll_handshake() {
+                    Ok(done) => !done,
+                    Err(e) => return Poll::Ready(Err(e)),
+                },
+                None => false,
+            };
+            let writing = this.chan.get_ref().has_pending_output() || !handshaking;
+            let ready = if writing {
+                this.chan.inner.poll_write_ready_mut(cx)
+            } else {
+                this.chan.inner.poll_read_ready_mut(cx)
+            };
+            let mut guard = match ready {
+                Poll::Ready(Ok(guard)) => guard,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(Error::Io(e))),
+                Poll::Pending => return Poll::Pending,
+            };
+            match send_some(guard.get_inner_mut(), &mut this.message) {
+                Ok(true) => return Poll::Ready(Ok(())),
+                Ok(false) => (),
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+            let chan = guard.get_inner();
+            let blocked = if writing { chan.has_pending_output() } else { !fd_readable(chan.fd()) };
+            if blocked {
+                guard.clear_ready();
+            }
+        }
+    }
+}
+
+
+// Descriptor owned by the ScheduleDiscoverer; registering it with the
+// reactor must not close it when the registration is dropped.
+struct BorrowedFd(RawFd);
+
+impl AsRawFd for BorrowedFd
+{
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+struct Watch {
+    fd: RawFd,
+    writable: bool,
+    afd: AsyncFd<BorrowedFd>,
+}
+
+// Fields are dropped in declaration order, so the watch goes first: its
+// registration must be removed before the discoverer closes the descriptor.
+struct Discover {
+    watch: Option<Watch>,
+    disco: Option<ScheduleDiscoverer>,
+    error: Option<Error>,
+    tick: Option<Pin<Box<Sleep>>>,
+}
+
+impl Future for Discover
+{
+    type Output = Result<MessageChannel>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if let Some(e) = this.error.take() {
+            return Poll::Ready(Err(e));
+        }
+        loop {
+            let wait_fd = {
+                let disco = this.disco.as_mut().expect("Discover polled after completion");
+                if let Some(chan) = disco.try_get_scheduler() {
+                    this.watch = None;
+                    this.disco = None;
+                    return Poll::Ready(Ok(chan));
+                }
+                if disco.timed_out() {
+                    let error = disco.take_error().unwrap_or(Error::Timeout);
+                    this.watch = None;
+                    this.disco = None;
+                    return Poll::Ready(Err(error));
+                }
+                disco.wait_fd()
+            };
+
+            let stale = match (this.watch.as_ref(), wait_fd) {
+                (Some(w), Some((fd, writable))) => w.fd != fd || w.writable != writable,
+                (None, None) => false,
+                _ => true,
+            };
+            if stale {
+                this.watch = None;
+                if let Some((fd, writable)) = wait_fd {
+                    let interest = if writable { Interest::WRITABLE } else { Interest::READABLE };
+                    match AsyncFd::with_interest(BorrowedFd(fd), interest) {
+                        Ok(afd) => this.watch = Some(Watch { fd, writable, afd }),
+                        Err(e) => return Poll::Ready(Err(Error::Io(e))),
+                    }
+                }
+            }
+
+            if let Some(ref watch) = this.watch {
+                let ready = if watch.writable {
+                    watch.afd.poll_write_ready(cx)
+                } else {
+                    watch.afd.poll_read_ready(cx)
+                };
+                match ready {
+                    Poll::Ready(Ok(mut guard)) => {
+                        guard.clear_ready();
+                        continue;
+                    },
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(Error::Io(e))),
+                    Poll::Pending => (),
+                }
+            }
+
+            let tick = this.tick.get_or_insert_with(|| {
+                Box::pin(time::sleep(Duration::from_millis(DISCOVER_POLL_INTERVAL_MS as u64)))
+            });
+            match tick.as_mut().poll(cx) {
+                Poll::Ready(()) => this.tick = None,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+// Must be awaited from within a Tokio runtime.
+pub fn discover(netname: Option<&str>, timeout: Duration)
+    -> impl Future<Output = Result<MessageChannel>>
+{
+    let disco = ScheduleDiscoverer::try_new_with_options(netname.unwrap_or(""), "",
+                                                         timeout_secs(timeout) as u32);
+    let (disco, error) = match disco {
+        Ok(disco) => (Some(disco), None),
+        Err(e) => (None, Some(e)),
+    };
+    Discover { watch: None, disco, error, tick: None }
+}
+
+
+#[cfg(all(test, feature = "pure-rust"))]
+mod tests {
+    use std::future;
+    use std::io::{ Read, Write };
+    use std::net::{ TcpListener, TcpStream };
+    use std::pin::Pin;
+    use std::time::Duration;
+
+    use futures_core::Stream;
+    use tokio::runtime;
+    use tokio::time;
+
+    use { Message, MessageChannel, msg };
+    use super::AsyncMessageChannel;
+
+    #[test]
+    fn send_after_stream_drained() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+
+        let rt = runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let _enter = rt.enter();
+        let mut chan = AsyncMessageChannel::new(MessageChannel::from_tcp(stream).unwrap()).unwrap();
+        // Accept whichever version the channel announces.
+        let mut version = [0u8; 4];
+        peer.read_exact(&mut version).unwrap();
+        peer.write_all(&version).unwrap();
+        peer.write_all(&version).unwrap();
+
+        // Completes the handshake and leaves nothing to read.
+        let next = future::poll_fn(|cx| Pin::new(&mut chan).poll_next(cx));
+        assert!(rt.block_on(time::timeout(Duration::from_millis(100), next)).is_err());
+
+        let send = chan.send(Message::from(msg::Ping::new()));
+        rt.block_on(time::timeout(Duration::from_secs(5), send)).expect("send stalled").unwrap();
+
+        let mut buf = [0u8; 12];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[4..], [0, 0, 0, 4, 0, 0, 0, 66]);
+    }
+}
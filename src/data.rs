@@ -0,0 +1,253 @@
+//
+// data.rs
+// Copyright (C) 2017 Adrian Perez <aperez@igalia.com>
+// Distributed under terms of the MIT license.
+//
+
+#[cfg(feature = "serde")]
+use serde::{ Deserialize, Serialize };
+
+use super::Language;
+
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CompileJobData {
+    pub job_id: u32,
+    pub language: Language,
+    pub compiler_name: String,
+    pub environment_version: String,
+    pub input_file: String,
+    pub output_file: String,
+    pub target_platform: String,
+    pub working_directory: String,
+    pub remote_flags: Vec<String>,
+    pub rest_flags: Vec<String>,
+    pub dwarf_fission: bool,
+}
+
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PingData;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EndData;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GetNativeEnvData {
+    pub compiler: String,
+    pub extra_files: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NativeEnvData {
+    pub native_version: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GetCSData {
+    pub environments: Vec<(String, String)>,
+    pub filename: String,
+    pub language: Language,
+    pub count: u32,
+    pub target: String,
+    pub arg_flags: u32,
+    pub client_id: u32,
+    pub preferred_host: String,
+    pub minimal_host_version: u32,
+    pub required_features: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct UseCSData {
+    pub job_id: u32,
+    pub port: u32,
+    pub host_name: String,
+    pub host_platform: String,
+    pub got_env: bool,
+    pub client_id: u32,
+    pub matched_job_id: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CompileFileData {
+    pub job: CompileJobData,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FileChunkData {
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CompileResultData {
+    pub stderr: String,
+    pub stdout: String,
+    pub status: i32,
+    pub was_out_of_memory: bool,
+    pub have_dwo_file: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct JobBeginData {
+    pub job_id: u32,
+    pub start_time: u32,
+    pub client_count: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct JobDoneData {
+    pub job_id: u32,
+    pub exit_code: i32,
+    pub real_msec: u32,
+    pub user_msec: u32,
+    pub sys_msec: u32,
+    pub page_faults: u32,
+    pub in_compressed: u32,
+    pub in_uncompressed: u32,
+    pub out_compressed: u32,
+    pub out_uncompressed: u32,
+    pub flags: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LocalJobBeginData {
+    pub start_time: u32,
+    pub output_file: String,
+    pub job_id: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LocalJobDoneData {
+    pub job_id: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LoginData {
+    pub port: u32,
+    pub max_jobs: u32,
+    pub environments: Vec<(String, String)>,
+    pub node_name: String,
+    pub host_platform: String,
+    pub chroot_possible: bool,
+    pub noremote: bool,
+    pub supported_features: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ConfCSData {
+    pub max_scheduler_pong: u32,
+    pub max_scheduler_ping: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StatsData {
+    pub load: u32,
+    pub niceload: u32,
+    pub load_avg1: u32,
+    pub load_avg5: u32,
+    pub load_avg10: u32,
+    pub free_mem: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EnvTransferData {
+    pub name: String,
+    pub target: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct InternalStatusData;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MonitorLoginData;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MonitorGetCSData {
+    pub filename: String,
+    pub language: Language,
+    pub job_id: u32,
+    pub client_id: u32,
+    pub target: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MonitorJobBeginData {
+    pub job_id: u32,
+    pub start_time: u32,
+    pub host_id: u32,
+}
+
+// The monitor variant is sent with its own type code, but carries exactly
+// the same fields as the job completion message.
+pub type MonitorJobDoneData = JobDoneData;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MonitorLocalJobBeginData {
+    pub host_id: u32,
+    pub job_id: u32,
+    pub start_time: u32,
+    pub filename: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MonitorStatsData {
+    pub host_id: u32,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TextData {
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StatusTextData {
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VerifyEnvData {
+    pub environment: String,
+    pub target: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VerifyEnvResultData {
+    pub ok: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BlacklistHostEnvData {
+    pub environment: String,
+    pub target: String,
+    pub host_name: String,
+}
@@ -0,0 +1,133 @@
+//
+// error.rs
+// Copyright (C) 2017 Adrian Perez <aperez@igalia.com>
+// Distributed under terms of the MIT license.
+//
+
+use std::error;
+use std::ffi::NulError;
+use std::fmt;
+use std::io;
+use std::result;
+use std::str::Utf8Error;
+use std::string::FromUtf8Error;
+
+
+#[derive(Debug)]
+pub enum Error {
+    /// A string passed to libicecc contained an interior NUL byte.
+    Nul(NulError),
+    /// A string returned by libicecc was not valid UTF-8.
+    Utf8(Utf8Error),
+    /// libicecc returned a null pointer.
+    NullPointer,
+    /// A message with an unknown (or unused) type code was received.
+    UnknownMessageType(u32),
+    /// No message arrived in the allotted time.
+    Timeout,
+    /// The remote end closed the channel.
+    Eof,
+    /// The channel is in an error state, or a message could not be decoded.
+    Protocol,
+    /// An I/O error occurred while waiting on the channel.
+    Io(io::Error),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RecvError {
+    /// No message arrived in the allotted time; the channel is still usable.
+    TimedOut,
+    /// The remote end closed the channel.
+    Closed,
+    /// A message was received, but it could not be decoded.
+    Malformed,
+    /// An I/O error occurred while waiting on the channel.
+    Io(io::ErrorKind),
+}
+
+
+impl fmt::Display for Error
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Nul(ref e) => write!(f, "invalid string: {}", e),
+            Error::Utf8(ref e) => write!(f, "invalid string: {}", e),
+            Error::NullPointer => write!(f, "unexpected null pointer"),
+            Error::UnknownMessageType(t) => write!(f, "unknown message type {}", t),
+            Error::Timeout => write!(f, "timed out"),
+            Error::Eof => write!(f, "end of file"),
+            Error::Protocol => write!(f, "protocol error"),
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error
+{
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Nul(ref e) => Some(e),
+            Error::Utf8(ref e) => Some(e),
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<RecvError> for Error
+{
+    fn from(e: RecvError) -> Self {
+        match e {
+            RecvError::TimedOut => Error::Timeout,
+            RecvError::Closed => Error::Eof,
+            RecvError::Malformed => Error::Protocol,
+            RecvError::Io(kind) => Error::Io(kind.into()),
+        }
+    }
+}
+
+impl From<io::Error> for Error
+{
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<NulError> for Error
+{
+    fn from(e: NulError) -> Self {
+        Error::Nul(e)
+    }
+}
+
+impl From<Utf8Error> for Error
+{
+    fn from(e: Utf8Error) -> Self {
+        Error::Utf8(e)
+    }
+}
+
+impl From<FromUtf8Error> for Error
+{
+    fn from(e: FromUtf8Error) -> Self {
+        Error::Utf8(e.utf8_error())
+    }
+}
+
+
+impl fmt::Display for RecvError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RecvError::TimedOut => write!(f, "timed out waiting for a message"),
+            RecvError::Closed => write!(f, "channel closed"),
+            RecvError::Malformed => write!(f, "malformed message"),
+            RecvError::Io(kind) => write!(f, "I/O error: {}", kind),
+        }
+    }
+}
+
+impl error::Error for RecvError {}
@@ -0,0 +1,723 @@
+//
+// ffi.rs
+// Copyright (C) 2017 Adrian Perez <aperez@igalia.com>
+// Distributed under terms of the MIT license.
+//
+
+use std::ffi::{ CStr, CString };
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+use libc::{ self, c_char, c_int };
+use sys;
+
+use super::{ CompileJob, Error, Language, Message, Result };
+use super::timeout_secs;
+
+
+impl From<sys::CompileJobLanguage> for Language
+{
+    fn from(lang: sys::CompileJobLanguage) -> Self {
+        match lang {
+            sys::CompileJobLanguage::C => Language::C,
+            sys::CompileJobLanguage::CXX => Language::CPlusPlus,
+            sys::CompileJobLanguage::OBJC => Language::ObjectiveC,
+            sys::CompileJobLanguage::CUSTOM => Language::Custom,
+        }
+    }
+}
+
+impl From<Language> for sys::CompileJobLanguage
+{
+    fn from(lang: Language) -> Self {
+        match lang {
+            Language::C => sys::CompileJobLanguage::C,
+            Language::CPlusPlus => sys::CompileJobLanguage::CXX,
+            Language::ObjectiveC => sys::CompileJobLanguage::OBJC,
+            Language::Custom => sys::CompileJobLanguage::CUSTOM,
+        }
+    }
+}
+
+
+pub trait AsPtr {
+    type Output;
+    fn as_ptr(&self) -> *mut Self::Output;
+}
+
+pub trait Base: AsPtr {
+    fn as_raw_ptr(&self) -> *mut sys::Msg {
+        self.as_ptr() as *mut sys::Msg
+    }
+}
+
+
+macro_rules! implement_ptrs {
+    ($(($name:ident $sysfree:ident))+) => {
+        $(
+            pub struct $name(pub *mut $crate::sys::$name);
+
+            impl Drop for $name {
+                fn drop(&mut self) {
+                    unsafe { $crate::sys::$sysfree(self.0) };
+                }
+            }
+
+            impl AsPtr for $name {
+                type Output = $crate::sys::$name;
+                fn as_ptr(&self) -> *mut Self::Output {
+                    self.0
+                }
+            }
+
+            // Each wrapper is the sole owner of its C++ object, so moving it
+            // to another thread is fine. It is not Sync: libicecc objects
+            // must not be used from more than one thread at a time.
+            unsafe impl Send for $name {}
+        )+
+    }
+}
+
+
+pub mod ptr {
+    use super::AsPtr;
+
+    implement_ptrs! {
+        (CompileJob compile_job_free)
+        (DiscoverSched discover_sched_free)
+        (MsgChannel msg_channel_free)
+        (Msg msg_free)
+    }
+
+    impl Msg {
+        pub fn message_type(&self) -> super::sys::MsgType {
+            unsafe { super::sys::msg_get_type(self.as_ptr()) }
+        }
+    }
+}
+
+
+macro_rules! accessor_simple {
+    (($t:ty) $fget:ident $sysfget:ident $fset:ident $sysfset:ident) => {
+        pub fn $fget(&self) -> $t {
+            unsafe { $crate::sys::$sysfget(self.as_ptr()) }.into()
+        }
+        pub fn $fset(&mut self, value: $t) {
+            let v = value.into();
+            unsafe { $crate::sys::$sysfset(self.as_ptr(), v) };
+        }
+    }
+}
+
+macro_rules! accessor_string {
+    ($fget:ident $tryfget:ident $sysfget:ident $fset:ident $tryfset:ident $sysfset:ident) => {
+        pub fn $fget(&self) -> String {
+            self.$tryfget().unwrap()
+        }
+
+        pub fn $tryfget(&self) -> $crate::Result<String> {
+            unsafe { $crate::ffi::take_string($crate::sys::$sysfget(self.as_ptr())) }
+        }
+
+        pub fn $fset(&mut self, value: &str) {
+            self.$tryfset(value).unwrap()
+        }
+
+        pub fn $tryfset(&mut self, value: &str) -> $crate::Result<()> {
+            let cs = ::std::ffi::CString::new(value)?;
+            unsafe { $crate::sys::$sysfset(self.as_ptr(), cs.as_ptr()) };
+            Ok(())
+        }
+    }
+}
+macro_rules! accessor_strings {
+    ($fget:ident $tryfget:ident $sysfget:ident $fset:ident $tryfset:ident $sysfset:ident) => {
+        pub fn $fget(&self) -> Vec<String> {
+            self.$tryfget().unwrap()
+        }
+
+        pub fn $tryfget(&self) -> $crate::Result<Vec<String>> {
+            unsafe { $crate::ffi::take_strings($crate::sys::$sysfget(self.as_ptr())) }
+        }
+
+        pub fn $fset<S: AsRef<str>>(&mut self, value: &[S]) {
+            self.$tryfset(value).unwrap()
+        }
+
+        pub fn $tryfset<S: AsRef<str>>(&mut self, value: &[S]) -> $crate::Result<()> {
+            let ptr = self.as_ptr();
+            $crate::ffi::with_c_strings(value.iter().map(AsRef::as_ref), |list, len| unsafe {
+                $crate::sys::$sysfset(ptr, list, len)
+            })
+        }
+    }
+}
+
+macro_rules! accessor_environments {
+    ($fget:ident $tryfget:ident $sysfget:ident $fset:ident $tryfset:ident $sysfset:ident) => {
+        pub fn $fget(&self) -> Vec<(String, String)> {
+            self.$tryfget().unwrap()
+        }
+
+        pub fn $tryfget(&self) -> $crate::Result<Vec<(String, String)>> {
+            unsafe { $crate::ffi::take_environments($crate::sys::$sysfget(self.as_ptr())) }
+        }
+
+        pub fn $fset<S: AsRef<str>>(&mut self, value: &[(S, S)]) {
+            self.$tryfset(value).unwrap()
+        }
+
+        pub fn $tryfset<S: AsRef<str>>(&mut self, value: &[(S, S)]) -> $crate::Result<()> {
+            let ptr = self.as_ptr();
+            $crate::ffi::with_c_strings(
+                value.iter().flat_map(|&(ref platform, ref version)| vec![platform.as_ref(), version.as_ref()]),
+                |list, len| unsafe { $crate::sys::$sysfset(ptr, list, len / 2) })
+        }
+    }
+}
+
+macro_rules! accessor_job {
+    ($fget:ident $tryfget:ident $sysfget:ident $fset:ident $tryfset:ident $sysfset:ident) => {
+        pub fn $fget(&self) -> $crate::CompileJob {
+            self.$tryfget().unwrap()
+        }
+
+        // libicecc hands out a copy of the job, owned by the caller.
+        pub fn $tryfget(&self) -> $crate::Result<$crate::CompileJob> {
+            $crate::CompileJob::from_raw_ptr(unsafe { $crate::sys::$sysfget(self.as_ptr()) })
+        }
+
+        pub fn $fset(&mut self, job: &$crate::CompileJob) {
+            self.$tryfset(job).unwrap()
+        }
+
+        // The other way around, libicecc stores a copy of the job in the
+        // message (which frees it along with itself), so the job passed in
+        // only needs to outlive the call, and remains owned by the caller.
+        pub fn $tryfset(&mut self, job: &$crate::CompileJob) -> $crate::Result<()> {
+            unsafe { $crate::sys::$sysfset(self.as_ptr(), job.as_ptr()) };
+            Ok(())
+        }
+    }
+}
+
+macro_rules! accessor_bytes {
+    ($fget:ident $sysfget:ident $fset:ident $sysfset:ident) => {
+        // The data is owned by the message, and valid for as long as it is
+        // not modified.
+        pub fn $fget(&self) -> &[u8] {
+            let mut len = 0;
+            let data = unsafe { $crate::sys::$sysfget(self.as_ptr(), &mut len) };
+            if data.is_null() || len == 0 {
+                &[]
+            } else {
+                unsafe { ::std::slice::from_raw_parts(data, len) }
+            }
+        }
+
+        pub fn $fset(&mut self, value: &[u8]) {
+            unsafe { $crate::sys::$sysfset(self.as_ptr(), value.as_ptr(), value.len()) };
+        }
+    }
+}
+
+
+// Takes ownership of a string allocated by libicecc.
+pub unsafe fn take_string(ptr: *mut c_char) -> Result<String> {
+    if ptr.is_null() {
+        return Err(Error::NullPointer);
+    }
+    let s = CStr::from_ptr(ptr).to_str().map(String::from);
+    libc::free(ptr as *mut libc::c_void);
+    Ok(s?)
+}
+
+// Takes ownership of a NULL-terminated array of strings allocated by
+// libicecc, including the strings themselves.
+pub unsafe fn take_strings(ptr: *mut *mut c_char) -> Result<Vec<String>> {
+    if ptr.is_null() {
+        return Err(Error::NullPointer);
+    }
+    let mut list = Vec::new();
+    let mut result = Ok(());
+    let mut i = 0;
+    while !(*ptr.add(i)).is_null() {
+        match take_string(*ptr.add(i)) {
+            Ok(s) => list.push(s),
+            Err(e) => result = Err(e),
+        }
+        i += 1;
+    }
+    libc::free(ptr as *mut libc::c_void);
+    result.map(|_| list)
+}
+
+// Environments are passed to and from libicecc as flat lists alternating
+// the platform and the version of each environment.
+pub unsafe fn take_environments(ptr: *mut *mut c_char) -> Result<Vec<(String, String)>> {
+    let mut list = take_strings(ptr)?.into_iter();
+    let mut envs = Vec::new();
+    while let (Some(platform), Some(version)) = (list.next(), list.next()) {
+        envs.push((platform, version));
+    }
+    Ok(envs)
+}
+
+pub fn with_c_strings<'a, I, F>(list: I, f: F) -> Result<()>
+    where I: IntoIterator<Item = &'a str>, F: FnOnce(*const *const c_char, usize)
+{
+    let strings = list.into_iter().map(CString::new).collect::<::std::result::Result<Vec<_>, _>>()?;
+    let mut ptrs: Vec<*const c_char> = strings.iter().map(|s| s.as_ptr()).collect();
+    let len = ptrs.len();
+    ptrs.push(::std::ptr::null());
+    f(ptrs.as_ptr(), len);
+    Ok(())
+}
+
+
+macro_rules! data_get {
+    (String $p:ident $get:ident) => {
+        unsafe { $crate::ffi::take_string($crate::sys::$get($p)) }
+    };
+    (Strings $p:ident $get:ident) => {
+        unsafe { $crate::ffi::take_strings($crate::sys::$get($p)) }
+    };
+    (Environments $p:ident $get:ident) => {
+        unsafe { $crate::ffi::take_environments($crate::sys::$get($p)) }
+    };
+    (Bytes $p:ident $get:ident) => {{
+        let mut len = 0;
+        let data = unsafe { $crate::sys::$get($p, &mut len) };
+        if data.is_null() && len > 0 {
+            Err($crate::Error::NullPointer)
+        } else if len == 0 {
+            Ok(Vec::new())
+        } else {
+            Ok(unsafe { ::std::slice::from_raw_parts(data, len) }.to_vec())
+        }
+    }};
+    (Job $p:ident $get:ident) => {
+        $crate::CompileJob::from_raw_ptr(unsafe { $crate::sys::$get($p) })
+            .and_then(|job| ::std::convert::TryFrom::try_from(&job))
+    };
+    ($t:ident $p:ident $get:ident) => {
+        Ok::<_, $crate::Error>(unsafe { $crate::sys::$get($p) }.into())
+    };
+}
+
+macro_rules! data_set {
+    (String $p:ident $set:ident $value:expr) => {{
+        let s = ::std::ffi::CString::new($value)?;
+        unsafe { $crate::sys::$set($p, s.as_ptr()) };
+    }};
+    (Strings $p:ident $set:ident $value:expr) => {
+        $crate::ffi::with_c_strings($value.iter().map(String::as_str), |list, len| unsafe {
+            $crate::sys::$set($p, list, len)
+        })?
+    };
+    (Environments $p:ident $set:ident $value:expr) => {
+        $crate::ffi::with_c_strings(
+            $value.iter().flat_map(|&(ref platform, ref version)| vec![platform.as_str(), version.as_str()]),
+            |list, len| unsafe { $crate::sys::$set($p, list, len / 2) })?
+    };
+    (Bytes $p:ident $set:ident $value:expr) => {
+        unsafe { $crate::sys::$set($p, $value.as_ptr(), $value.len()) }
+    };
+    // The message keeps its own copy, so the temporary job can be dropped.
+    (Job $p:ident $set:ident $value:expr) => {{
+        let job = <$crate::CompileJob as ::std::convert::TryFrom<_>>::try_from($value)?;
+        unsafe { $crate::sys::$set($p, job.as_ptr()) };
+    }};
+    ($t:ident $p:ident $set:ident $value:expr) => {
+        unsafe { $crate::sys::$set($p, $value.into()) }
+    };
+}
+
+macro_rules! implement_messages {
+    ($($name:ident($data:ident) => $sysname:ident $(($sysnew:ident))* { $( $rest:tt )* })+) => {
+        $(
+            pub struct $name {
+                msg: $crate::ffi::ptr::Msg,
+            }
+
+            impl $crate::ffi::Base for $name {}
+
+            impl $crate::ffi::AsPtr for $name {
+                type Output = $crate::sys::$sysname;
+                fn as_ptr(&self) -> *mut Self::Output {
+                    self.msg.as_ptr() as *mut Self::Output
+                }
+            }
+
+            impl From<$crate::ffi::ptr::Msg> for $name {
+                fn from(msg: $crate::ffi::ptr::Msg) -> Self {
+                    Self { msg }
+                }
+            }
+
+            impl From<$name> for $crate::Message {
+                fn from(msg: $name) -> $crate::Message {
+                    $crate::Message::$name(msg)
+                }
+            }
+
+            impl $name {
+                #[allow(dead_code)]
+                fn from_raw_ptr(ptr: *mut <$name as $crate::ffi::AsPtr>::Output) -> $crate::Result<Self> {
+                    if ptr.is_null() {
+                        Err($crate::Error::NullPointer)
+                    } else {
+                        Ok($name::from($crate::ffi::ptr::Msg(ptr as *mut $crate::sys::Msg)))
+                    }
+                }
+
+                $(
+                    pub(crate) fn alloc() -> $crate::Result<Self> {
+                        $name::from_raw_ptr(unsafe { $crate::sys::$sysnew() })
+                    }
+                )*
+
+                $( $rest )*
+            }
+        )+
+    }
+}
+
+
+macro_rules! implement_data {
+    ($($name:path => $data:ident { $(($kind:ident $field:ident $get:ident $set:ident))* })+) => {
+        $(
+            impl<'a> ::std::convert::TryFrom<&'a $name> for $crate::data::$data {
+                type Error = $crate::Error;
+
+                #[allow(unused_variables)]
+                fn try_from(m: &'a $name) -> $crate::Result<Self> {
+                    let p = m.as_ptr();
+                    Ok($crate::data::$data { $( $field: data_get!($kind p $get)?, )* })
+                }
+            }
+
+            impl ::std::convert::TryFrom<$crate::data::$data> for $name {
+                type Error = $crate::Error;
+
+                #[allow(unused_variables)]
+                fn try_from(data: $crate::data::$data) -> $crate::Result<Self> {
+                    let m = <$name>::alloc()?;
+                    let p = m.as_ptr();
+                    $( data_set!($kind p $set data.$field); )*
+                    Ok(m)
+                }
+            }
+        )+
+
+        implement_fmt! { $( $name => $data { $(($kind $field $get $set))* } )+ }
+    }
+}
+
+
+pub struct ScheduleDiscoverer {
+    sd: ptr::DiscoverSched,
+}
+
+impl ScheduleDiscoverer
+{
+    fn from_raw_ptr(ptr: *mut sys::DiscoverSched) -> Result<Self> {
+        if ptr.is_null() {
+            Err(Error::NullPointer)
+        } else {
+            Ok(Self { sd: ptr::DiscoverSched(ptr) })
+        }
+    }
+
+    pub fn try_new<'f, T: Into<Option<&'f String>>>(netname: T) -> Result<Self> {
+        match netname.into() {
+            None => Self::from_raw_ptr(unsafe {
+                sys::discover_sched_new(::std::ptr::null())
+            }),
+            Some(name) => {
+                let s = CString::new(name.as_bytes())?;
+                Self::from_raw_ptr(unsafe { sys::discover_sched_new(s.as_ptr()) })
+            },
+        }
+    }
+
+    pub fn try_new_with_options(netname: &str, scheduler: &str, timeout: u32) -> Result<Self> {
+        let c_netname = CString::new(netname)?;
+        let c_scheduler = CString::new(scheduler)?;
+        Self::from_raw_ptr(unsafe {
+            sys::discover_sched_new_with_options(c_netname.as_ptr(),
+                                                 c_scheduler.as_ptr(),
+                                                 timeout as c_int)
+        })
+    }
+
+    pub fn timed_out(&mut self) -> bool {
+        unsafe { sys::discover_sched_timed_out(self.sd.as_ptr()) }
+    }
+
+    // libicecc only logs the reason connecting to a scheduler failed.
+    pub fn take_error(&mut self) -> Option<Error> {
+        None
+    }
+
+    pub fn listen_fd(&self) -> c_int {
+        unsafe { sys::discover_sched_listen_fd(self.sd.as_ptr()) }
+    }
+
+    pub fn connect_fd(&self) -> c_int {
+        unsafe { sys::discover_sched_connect_fd(self.sd.as_ptr()) }
+    }
+
+    pub fn try_get_scheduler(&mut self) -> Option<MessageChannel> {
+        let ptr = unsafe { sys::discover_sched_try_get_scheduler(self.sd.as_ptr()) };
+        if ptr.is_null() {
+            None
+        } else {
+            Some(MessageChannel::from_raw_ptr(ptr))
+        }
+    }
+}
+
+
+pub struct MessageChannel {
+    mc: ptr::MsgChannel,
+}
+
+impl MessageChannel
+{
+    fn from_raw_ptr(ptr: *mut sys::MsgChannel) -> Self {
+        assert!(!ptr.is_null());
+        Self {
+            mc: ptr::MsgChannel(ptr)
+        }
+    }
+
+    // libicecc only reports failure to connect with a null pointer, the
+    // cause is left in errno.
+    fn from_new_ptr(ptr: *mut sys::MsgChannel) -> Result<Self> {
+        if ptr.is_null() {
+            Err(Error::Io(io::Error::last_os_error()))
+        } else {
+            Ok(Self::from_raw_ptr(ptr))
+        }
+    }
+
+    pub(crate) fn connect(addr: &SocketAddr, timeout: Duration) -> Result<Self> {
+        let host = CString::new(addr.ip().to_string())?;
+        Self::from_new_ptr(unsafe {
+            sys::msg_channel_new_tcp(host.as_ptr(), addr.port() as c_int, timeout_secs(timeout))
+        })
+    }
+
+    pub(crate) fn connect_unix(path: &Path) -> Result<Self> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        Self::from_new_ptr(unsafe { sys::msg_channel_new_unix(path.as_ptr()) })
+    }
+
+    pub fn fd(&self) -> c_int {
+        unsafe { sys::msg_channel_fd(self.mc.as_ptr()) }
+    }
+
+    pub fn bulk_transfer(&mut self) {
+        unsafe { sys::msg_channel_set_bulk_transfer(self.mc.as_ptr()) };
+    }
+
+    pub fn read_a_bit(&mut self) -> bool {
+        unsafe { sys::msg_channel_read_a_bit(self.mc.as_ptr()) }
+    }
+
+    pub fn eof(&self) -> bool {
+        unsafe { sys::msg_channel_at_eof(self.mc.as_ptr()) }
+    }
+
+    pub fn is_text_based(&self) -> bool {
+        unsafe { sys::msg_channel_is_text_based(self.mc.as_ptr()) }
+    }
+
+    pub fn has_message(&self) -> bool {
+        unsafe { sys::msg_channel_has_msg(self.mc.as_ptr()) }
+    }
+
+    pub fn try_recv(&mut self, timeout: Option<Duration>) -> Result<Message> {
+        let ptr = match timeout {
+            None => unsafe { sys::msg_channel_get_msg(self.mc.as_ptr()) },
+            Some(t) => unsafe {
+                sys::msg_channel_get_msg_with_timeout(self.mc.as_ptr(), timeout_secs(t))
+            },
+        };
+        // Without a timeout libicecc still gives up after its default
+        // one, which is reported the same way.
+        if !ptr.is_null() {
+            Message::try_from_raw_ptr(ptr)
+        } else if self.eof() {
+            Err(Error::Eof)
+        } else if !self.has_message() {
+            Err(Error::Timeout)
+        } else {
+            Err(Error::Protocol)
+        }
+    }
+
+    // libicecc negotiates the protocol version as input arrives, and the
+    // channel reports no version until the peer has confirmed it.
+    fn protocol(&self) -> c_int {
+        unsafe { sys::msg_channel_protocol(self.mc.as_ptr()) }
+    }
+
+    pub fn poll_handshake(&mut self) -> Result<bool> {
+        if self.protocol() == 0 && !self.read_a_bit() {
+            return Err(Error::Protocol);
+        }
+        if self.protocol() != 0 {
+            Ok(true)
+        } else if self.eof() {
+            Err(Error::Eof)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn has_pending_output(&self) -> bool {
+        unsafe { sys::msg_channel_has_pending_output(self.mc.as_ptr()) }
+    }
+
+    // Writes as much of the pending output as possible without blocking,
+    // returning whether all of it has been written.
+    pub fn poll_flush(&mut self) -> Result<bool> {
+        if unsafe { sys::msg_channel_flush_writebuf(self.mc.as_ptr(), false) } {
+            Ok(!self.has_pending_output())
+        } else if self.eof() {
+            Err(Error::Eof)
+        } else {
+            Err(Error::Protocol)
+        }
+    }
+
+    // Encodes a message into the output buffer, to be written by later
+    // calls to poll_flush(). The handshake must have completed already.
+    pub fn start_send<M: AsRef<Message>>(&mut self, message: M) -> Result<()> {
+        if self.eof() {
+            return Err(Error::Eof);
+        }
+        if self.protocol() == 0 {
+            return Err(Error::Protocol);
+        }
+        let ptr = message.as_ref().as_raw_ptr();
+        if unsafe { sys::msg_send_to_channel_nonblocking(ptr, self.mc.as_ptr()) } {
+            Ok(())
+        } else if self.eof() {
+            Err(Error::Eof)
+        } else {
+            Err(Error::Protocol)
+        }
+    }
+
+    pub fn try_send<M: AsRef<Message>>(&mut self, message: M) -> Result<()> {
+        if self.eof() {
+            return Err(Error::Eof);
+        }
+        let ptr = message.as_ref().as_raw_ptr();
+        unsafe { sys::msg_send_to_channel(ptr, self.mc.as_ptr()) };
+        if self.eof() { Err(Error::Eof) } else { Ok(()) }
+    }
+}
+
+
+impl Message {
+    fn try_from_raw_ptr(ptr: *mut sys::Msg) -> Result<Self> {
+        let msg = ptr::Msg(ptr);
+        Ok(match msg.message_type() {
+            sys::MsgType::M_UNKNOWN =>
+                return Err(Error::UnknownMessageType(sys::MsgType::M_UNKNOWN as u32)),
+            sys::MsgType::M_PING => Message::Ping(msg.into()),
+            sys::MsgType::M_END => Message::End(msg.into()),
+            sys::MsgType::M_TIMEOUT => return Err(Error::Timeout),
+            sys::MsgType::M_GET_NATIVE_ENV => Message::GetNativeEnv(msg.into()),
+            sys::MsgType::M_NATIVE_ENV => Message::NativeEnv(msg.into()),
+            sys::MsgType::M_GET_CS => Message::GetCS(msg.into()),
+            sys::MsgType::M_USE_CS => Message::UseCS(msg.into()),
+            sys::MsgType::M_COMPILE_FILE => Message::CompileFile(msg.into()),
+            sys::MsgType::M_FILE_CHUNK => Message::FileChunk(msg.into()),
+            sys::MsgType::M_COMPILE_RESULT => Message::CompileResult(msg.into()),
+            sys::MsgType::M_JOB_BEGIN => Message::JobBegin(msg.into()),
+            sys::MsgType::M_JOB_DONE => Message::JobDone(msg.into()),
+            sys::MsgType::M_JOB_LOCAL_BEGIN => Message::LocalJobBegin(msg.into()),
+            sys::MsgType::M_JOB_LOCAL_DONE => Message::LocalJobDone(msg.into()),
+            sys::MsgType::M_LOGIN => Message::Login(msg.into()),
+            sys::MsgType::M_CS_CONF => Message::ConfCS(msg.into()),
+            sys::MsgType::M_STATS => Message::Stats(msg.into()),
+            sys::MsgType::M_TRANSFER_ENV => Message::EnvTransfer(msg.into()),
+            sys::MsgType::M_GET_INTERNALS => Message::InternalStatus(msg.into()),
+            sys::MsgType::M_MON_LOGIN => Message::MonitorLogin(msg.into()),
+            sys::MsgType::M_MON_GET_CS => Message::MonitorGetCS(msg.into()),
+            sys::MsgType::M_MON_JOB_BEGIN => Message::MonitorJobBegin(msg.into()),
+            sys::MsgType::M_MON_JOB_DONE => Message::MonitorJobDone(msg.into()),
+            sys::MsgType::M_MON_LOCAL_JOB_BEGIN => Message::MonitorLocalJobBegin(msg.into()),
+            sys::MsgType::M_MON_STATS => Message::MonitorStats(msg.into()),
+            sys::MsgType::M_TEXT => Message::Text(msg.into()),
+            sys::MsgType::M_STATUS_TEXT => Message::StatusText(msg.into()),
+            sys::MsgType::M_VERIFY_ENV => Message::VerifyEnv(msg.into()),
+            sys::MsgType::M_VERIFY_ENV_RESULT => Message::VerifyEnvResult(msg.into()),
+            sys::MsgType::M_BLACKLIST_HOST_ENV => Message::BlacklistHostEnv(msg.into()),
+        })
+    }
+
+    fn as_raw_ptr(&self) -> *mut sys::Msg {
+        match *self {
+            Message::Ping(ref m) => m.as_raw_ptr(),
+            Message::End(ref m) => m.as_raw_ptr(),
+            Message::GetNativeEnv(ref m) => m.as_raw_ptr(),
+            Message::NativeEnv(ref m) => m.as_raw_ptr(),
+            Message::GetCS(ref m) => m.as_raw_ptr(),
+            Message::UseCS(ref m) => m.as_raw_ptr(),
+            Message::CompileFile(ref m) => m.as_raw_ptr(),
+            Message::FileChunk(ref m) => m.as_raw_ptr(),
+            Message::CompileResult(ref m) => m.as_raw_ptr(),
+            Message::JobBegin(ref m) => m.as_raw_ptr(),
+            Message::JobDone(ref m) => m.as_raw_ptr(),
+            Message::LocalJobBegin(ref m) => m.as_raw_ptr(),
+            Message::LocalJobDone(ref m) => m.as_raw_ptr(),
+            Message::Login(ref m) => m.as_raw_ptr(),
+            Message::ConfCS(ref m) => m.as_raw_ptr(),
+            Message::Stats(ref m) => m.as_raw_ptr(),
+            Message::EnvTransfer(ref m) => m.as_raw_ptr(),
+            Message::InternalStatus(ref m) => m.as_raw_ptr(),
+            Message::MonitorLogin(ref m) => m.as_raw_ptr(),
+            Message::MonitorGetCS(ref m) => m.as_raw_ptr(),
+            Message::MonitorJobBegin(ref m) => m.as_raw_ptr(),
+            Message::MonitorJobDone(ref m) => m.as_raw_ptr(),
+            Message::MonitorLocalJobBegin(ref m) => m.as_raw_ptr(),
+            Message::MonitorStats(ref m) => m.as_raw_ptr(),
+            Message::Text(ref m) => m.as_raw_ptr(),
+            Message::StatusText(ref m) => m.as_raw_ptr(),
+            Message::VerifyEnv(ref m) => m.as_raw_ptr(),
+            Message::VerifyEnvResult(ref m) => m.as_raw_ptr(),
+            Message::BlacklistHostEnv(ref m) => m.as_raw_ptr(),
+        }
+    }
+}
+
+
+impl AsPtr for CompileJob {
+    type Output = sys::CompileJob;
+    fn as_ptr(&self) -> *mut sys::CompileJob {
+        self.cj.as_ptr()
+    }
+}
+
+impl CompileJob
+{
+    pub(crate) fn from_raw_ptr(ptr: *mut sys::CompileJob) -> Result<Self> {
+        if ptr.is_null() {
+            Err(Error::NullPointer)
+        } else {
+            Ok(Self { cj: ptr::CompileJob(ptr) })
+        }
+    }
+
+    pub(crate) fn alloc() -> Result<Self> {
+        Self::from_raw_ptr(unsafe { sys::compile_job_new() })
+    }
+}
@@ -4,248 +4,313 @@
 // Distributed under terms of the MIT license.
 //
 
+#[cfg(not(feature = "pure-rust"))]
 extern crate libicecc_sys as sys;
 extern crate libc;
-
-use std::convert::AsRef;
-use std::ffi::{ CStr, CString };
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "tokio")]
+extern crate futures_core;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+#[cfg(all(test, feature = "serde"))]
+extern crate bincode;
+
+#[cfg(not(any(feature = "libicecc-sys", feature = "pure-rust")))]
+compile_error!("either the \"libicecc-sys\" (default) or the \"pure-rust\" feature must be enabled");
+
+use std::convert::{ AsRef, TryFrom };
 use std::fmt;
-use std::rc::Rc;
-use libc::{ c_char, c_int, c_void };
-
-
-#[derive(Debug, Eq, PartialEq)]
-pub enum Language {
-    C,
-    CPlusPlus,
-    ObjectiveC,
-    Custom,
-}
-
-impl From<sys::CompileJobLanguage> for Language
-{
-    fn from(lang: sys::CompileJobLanguage) -> Self {
-        match lang {
-            sys::CompileJobLanguage::C => Language::C,
-            sys::CompileJobLanguage::CXX => Language::CPlusPlus,
-            sys::CompileJobLanguage::OBJC => Language::ObjectiveC,
-            sys::CompileJobLanguage::CUSTOM => Language::Custom,
-        }
-    }
-}
-
-impl From<Language> for sys::CompileJobLanguage
-{
-    fn from(lang: Language) -> Self {
-        match lang {
-            Language::C => sys::CompileJobLanguage::C,
-            Language::CPlusPlus => sys::CompileJobLanguage::CXX,
-            Language::ObjectiveC => sys::CompileJobLanguage::OBJC,
-            Language::Custom => sys::CompileJobLanguage::CUSTOM,
-        }
-    }
-}
+use std::fs::File;
+use std::io::{ self, Read, Write };
+use std::os::unix::io::{ AsRawFd, RawFd };
+use std::path::Path;
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
+use libc::c_int;
 
+mod error;
+pub use error::{ Error, RecvError, Result };
 
-trait AsPtr {
-    type Output;
-    fn as_ptr(&self) -> *mut Self::Output;
-}
+pub mod data;
 
+mod load;
 
-macro_rules! implement_ptrs {
-    ($(($name:ident $sysfree:ident))+) => {
-        $(
-            pub struct $name(pub *mut $crate::sys::$name);
+mod status;
+pub use status::{ InternalClient, InternalStatus };
 
-            impl Drop for $name {
-                fn drop(&mut self) {
-                    unsafe { $crate::sys::$sysfree(self.0) };
-                }
-            }
+mod monitor;
+pub use monitor::{ MonitorEvent, MonitorSession };
 
-            impl AsPtr for $name {
-                type Output = $crate::sys::$name;
-                fn as_ptr(&self) -> *mut Self::Output { 
-                    self.0
-                }
-            }
-        )+
-    }
-}
+mod local;
+pub use local::LocalJobGuard;
 
+#[cfg(not(feature = "pure-rust"))]
+#[macro_use]
+mod ffi;
+#[cfg(not(feature = "pure-rust"))]
+use ffi::AsPtr;
+#[cfg(not(feature = "pure-rust"))]
+pub use ffi::{ MessageChannel, ScheduleDiscoverer };
 
-mod ptr {
-    use super::AsPtr;
+#[cfg(feature = "pure-rust")]
+#[macro_use]
+mod native;
+#[cfg(feature = "pure-rust")]
+pub use native::{ MessageChannel, ScheduleDiscoverer };
 
-    implement_ptrs! {
-        (CompileJob compile_job_free)
-        (DiscoverSched discover_sched_free)
-        (MsgChannel msg_channel_free)
-        (Msg msg_free)
-    }
+#[cfg(feature = "serde")]
+use serde::{ Deserialize, Serialize };
 
-    impl Msg {
-        pub fn message_type(&self) -> super::sys::MsgType {
-            unsafe { super::sys::msg_get_type(self.as_ptr()) }
-        }
-    }
-}
+#[cfg(feature = "serde")]
+mod serialization;
 
+#[cfg(feature = "tokio")]
+mod async_io;
+#[cfg(feature = "tokio")]
+pub use async_io::{ AsyncMessageChannel, SendMessage, discover };
 
 
-#[derive(Clone)]
-pub struct ScheduleDiscoverer {
-    sd: Rc<ptr::DiscoverSched>,
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Language {
+    #[default]
+    C,
+    CPlusPlus,
+    ObjectiveC,
+    Custom,
 }
 
 impl ScheduleDiscoverer
 {
     pub fn new<'f, T: Into<Option<&'f String>>>(netname: T) -> Self {
-        match netname.into() {
-            None => Self {
-                sd: Rc::new(ptr::DiscoverSched(unsafe {
-                    sys::discover_sched_new(std::ptr::null())
-                }))
-            },
-            Some(name) => {
-                let s = CString::new(name.as_bytes()).unwrap();
-                Self {
-                    sd: Rc::new(ptr::DiscoverSched(unsafe {
-                        sys::discover_sched_new(s.as_ptr())
-                    }))
-                }
-            },
-        }
+        Self::try_new(netname).unwrap()
     }
 
     pub fn new_with_options(netname: &str, scheduler: &str, timeout: u32) -> Self {
-        let c_netname = CString::new(netname).unwrap();
-        let c_scheduler = CString::new(scheduler).unwrap();
-        Self {
-            sd: Rc::new(ptr::DiscoverSched(unsafe {
-                sys::discover_sched_new_with_options(c_netname.as_ptr(),
-                                                     c_scheduler.as_ptr(),
-                                                     timeout as c_int)
-            }))
-        }
+        Self::try_new_with_options(netname, scheduler, timeout).unwrap()
     }
 
-    pub fn timed_out(&mut self) -> bool {
-        unsafe { sys::discover_sched_timed_out(self.sd.as_ptr()) }
+    // Descriptor the discovery is currently waiting on, if any, and whether
+    // it needs to become writable (connection in progress) or readable
+    // (waiting for broadcast answers).
+    fn wait_fd(&self) -> Option<(c_int, bool)> {
+        let fd = self.connect_fd();
+        if fd >= 0 {
+            return Some((fd, true));
+        }
+        let fd = self.listen_fd();
+        if fd >= 0 { Some((fd, false)) } else { None }
     }
+}
 
-    pub fn listen_fd(&self) -> c_int {
-        unsafe { sys::discover_sched_listen_fd(self.sd.as_ptr()) }
-    }
-    
-    pub fn connect_fd(&self) -> c_int {
-        unsafe { sys::discover_sched_connect_fd(self.sd.as_ptr()) }
-    }
 
-    pub fn try_get_scheduler(&mut self) -> Option<MessageChannel> {
-        let ptr = unsafe { sys::discover_sched_try_get_scheduler(self.sd.as_ptr()) };
-        if ptr.is_null() {
-            None
-        } else {
-            Some(MessageChannel::from_raw_ptr(ptr))
+// Upper bound on how long discovery waits for descriptor activity before
+// checking again; the discoverer re-sends broadcasts and switches sockets
+// on its own schedule.
+const DISCOVER_POLL_INTERVAL_MS: c_int = 500;
+
+pub fn discover_blocking(netname: Option<&str>, timeout: Duration) -> Result<MessageChannel> {
+    let mut disco = ScheduleDiscoverer::try_new_with_options(netname.unwrap_or(""), "",
+                                                             timeout_secs(timeout) as u32)?;
+    loop {
+        if let Some(chan) = disco.try_get_scheduler() {
+            return Ok(chan);
+        }
+        if disco.timed_out() {
+            return Err(disco.take_error().unwrap_or(Error::Timeout));
+        }
+
+        let mut pfd = libc::pollfd { fd: -1, events: 0, revents: 0 };
+        if let Some((fd, writable)) = disco.wait_fd() {
+            pfd.fd = fd;
+            pfd.events = if writable { libc::POLLOUT } else { libc::POLLIN };
+        }
+        // Descriptors with a negative value are ignored by poll(), which
+        // then just waits for the interval to expire.
+        let ret = unsafe { libc::poll(&mut pfd, 1, DISCOVER_POLL_INTERVAL_MS) };
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                return Err(Error::Io(err));
+            }
         }
     }
 }
 
 
-#[derive(Clone)]
-pub struct MessageChannel {
-    mc: Rc<ptr::MsgChannel>,
-}
+pub type SharedMessageChannel = Arc<Mutex<MessageChannel>>;
+
+// Same chunk size used by icecc when transferring files.
+const FILE_CHUNK_SIZE: usize = 100 * 1024;
+
+// icecc also waits this long for the daemon to create a native environment.
+const NATIVE_ENV_TIMEOUT: Duration = Duration::from_secs(4 * 60);
 
 impl MessageChannel
 {
-    fn from_raw_ptr(ptr: *mut sys::MsgChannel) -> Self {
-        assert!(!ptr.is_null());
-        Self {
-            mc: Rc::new(ptr::MsgChannel(ptr))
-        }
+    // A channel cannot be duplicated (its buffers and protocol state are
+    // private to it), so sharing it requires a lock around it.
+    pub fn into_shared(self) -> SharedMessageChannel {
+        Arc::new(Mutex::new(self))
     }
 
-    pub fn fd(&self) -> c_int {
-        unsafe { sys::msg_channel_fd(self.mc.as_ptr()) }
+    pub fn recv(&mut self, timeout: Option<Duration>) -> std::result::Result<Message, RecvError> {
+        self.try_recv(timeout).map_err(|e| match e {
+            Error::Timeout => RecvError::TimedOut,
+            Error::Eof => RecvError::Closed,
+            Error::Io(ref e) => RecvError::Io(e.kind()),
+            _ => RecvError::Malformed,
+        })
     }
 
-    pub fn bulk_transfer(&mut self) {
-        unsafe { sys::msg_channel_set_bulk_transfer(self.mc.as_ptr()) };
-    }
-
-    pub fn read_a_bit(&mut self) -> bool {
-        unsafe { sys::msg_channel_read_a_bit(self.mc.as_ptr()) }
+    pub fn send<M: AsRef<Message>>(&mut self, message: M) {
+        let _ = self.try_send(message);
     }
 
-    pub fn eof(&self) -> bool {
-        unsafe { sys::msg_channel_at_eof(self.mc.as_ptr()) }
+    // Sends the contents of a reader as a sequence of FileChunk messages,
+    // followed by End. Returns the number of bytes sent.
+    pub fn send_file<R: Read>(&mut self, reader: R) -> Result<u64> {
+        self.send_file_with_progress(reader, |_| ())
     }
 
-    pub fn is_text_based(&self) -> bool {
-        unsafe { sys::msg_channel_is_text_based(self.mc.as_ptr()) }
+    // Same as send_file(), calling progress with the number of bytes sent
+    // so far after each chunk.
+    pub fn send_file_with_progress<R, F>(&mut self, mut reader: R, mut progress: F) -> Result<u64>
+        where R: Read, F: FnMut(u64)
+    {
+        let mut buf = vec![0; FILE_CHUNK_SIZE];
+        let mut total = 0;
+        loop {
+            let mut len = 0;
+            while len < buf.len() {
+                match reader.read(&mut buf[len..]) {
+                    Ok(0) => break,
+                    Ok(n) => len += n,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                    Err(e) => return Err(Error::Io(e)),
+                }
+            }
+            if len == 0 {
+                break;
+            }
+            self.try_send(Message::from(msg::FileChunk::try_new(&buf[..len])?))?;
+            total += len as u64;
+            progress(total);
+        }
+        self.try_send(Message::from(msg::End::try_new()?))?;
+        Ok(total)
     }
 
-    pub fn has_message(&self) -> bool {
-        unsafe { sys::msg_channel_has_msg(self.mc.as_ptr()) }
+    // Receives FileChunk messages into a writer until End arrives. Returns
+    // the number of bytes received.
+    pub fn recv_file<W: Write>(&mut self, mut writer: W) -> Result<u64> {
+        let mut total = 0;
+        loop {
+            match self.try_recv(None)? {
+                Message::FileChunk(chunk) => {
+                    writer.write_all(chunk.data())?;
+                    total += chunk.data().len() as u64;
+                },
+                Message::End(_) => break,
+                _ => return Err(Error::Protocol),
+            }
+        }
+        writer.flush()?;
+        Ok(total)
     }
 
-    pub fn recv(&mut self, timeout: Option<u32>) -> Option<Message> {
-        let ptr = match timeout {
-            None => unsafe { sys::msg_channel_get_msg(self.mc.as_ptr()) },
-            Some(t) => unsafe { sys::msg_channel_get_msg_with_timeout(self.mc.as_ptr(), t as c_int) },
-        };
-        if ptr.is_null() {
-            None
-        } else {
-            Some(Message::from_raw_ptr(ptr))
+    // Asks a compile server whether it is able to use an environment it
+    // has previously received, and waits for the answer. Unpacking a large
+    // environment may take a while, so the caller chooses how long to wait.
+    pub fn verify_environment(&mut self, target: &str, environment: &str,
+                              timeout: Duration) -> Result<bool> {
+        self.try_send(Message::from(msg::VerifyEnv::try_new(target, environment)?))?;
+        match self.try_recv(Some(timeout))? {
+            Message::VerifyEnvResult(result) => Ok(result.ok()),
+            _ => Err(Error::Protocol),
         }
     }
 
-    pub fn send<M: AsRef<Message>>(&mut self, message: M) {
-        let ptr = message.as_ref().as_raw_ptr();
-        unsafe { sys::msg_send_to_channel(ptr, self.mc.as_ptr()) }
+    // Tells the scheduler to stop sending jobs using an environment, given
+    // by its target platform and version, to a host.
+    pub fn blacklist(&mut self, host_name: &str, target: &str, environment: &str) -> Result<()> {
+        self.try_send(Message::from(msg::BlacklistHostEnv::try_new(host_name, target, environment)?))
     }
 }
 
 
-macro_rules! accessor_simple {
-    (($t:ty) $fget:ident $sysfget:ident $fset:ident $sysfset:ident) => {
-        pub fn $fget(&self) -> $t {
-            unsafe { $crate::sys::$sysfget(self.as_ptr()) }.into()
-        }
-        pub fn $fset(&mut self, value: $t) {
-            let v = value.into();
-            unsafe { $crate::sys::$sysfset(self.as_ptr(), v) };
-        }
+// Sends an environment tarball to a compile server, the way icecc does
+// before the first job using it. The progress callback gets the number of
+// bytes sent so far and the size of the file. Returns the number of bytes
+// sent; the environment may be checked afterwards with verify_environment().
+pub fn upload_environment<P, F>(channel: &mut MessageChannel, name: &str, target: &str, path: P,
+                                mut progress: F) -> Result<u64>
+    where P: AsRef<Path>, F: FnMut(u64, u64)
+{
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    channel.try_send(Message::from(msg::EnvTransfer::try_new(name, target)?))?;
+    channel.bulk_transfer();
+    channel.send_file_with_progress(file, |sent| progress(sent, size))
+}
+
+// Asks the local daemon for an environment tarball built from the given
+// compiler ("gcc" or "clang") plus any extra files, and returns its path.
+// Creating the environment may take a while the first time.
+pub fn request_native_env<S: AsRef<str>>(channel: &mut MessageChannel, compiler: &str,
+                                         extra_files: &[S]) -> Result<String> {
+    channel.try_send(Message::from(msg::GetNativeEnv::try_new(compiler, extra_files)?))?;
+    match channel.try_recv(Some(NATIVE_ENV_TIMEOUT))? {
+        Message::NativeEnv(env) => env.try_native_version(),
+        _ => Err(Error::Protocol),
     }
 }
 
-macro_rules! accessor_string {
-    ($fget:ident $sysfget:ident $fset:ident $sysfset:ident) => {
-        pub fn $fget(&self) -> String {
-            unsafe {
-                let ptr = $crate::sys::$sysfget(self.as_ptr());
-                assert_ne!(ptr, 0 as *mut c_char);
-                let s = String::from_utf8(CStr::from_ptr(ptr).to_bytes().to_vec()).unwrap();
-                libc::free(ptr as *mut c_void);
-                s
-            }
-        }
+// Asks a daemon for a dump of its internal state. The text may be parsed
+// with InternalStatus::parse().
+pub fn query_internal_status(channel: &mut MessageChannel) -> Result<String> {
+    channel.try_send(Message::from(msg::InternalStatus::try_new()?))?;
+    match channel.try_recv(None)? {
+        Message::StatusText(status) => status.try_text(),
+        _ => Err(Error::Protocol),
+    }
+}
 
-        pub fn $fset(&mut self, value: &str) {
-            let cs = CString::new(value).unwrap();
-            unsafe { $crate::sys::$sysfset(self.as_ptr(), cs.as_ptr()) };
-        }
+impl AsRawFd for MessageChannel
+{
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd()
     }
 }
 
+
+// libicecc takes timeouts in whole seconds; round up so that a short,
+// non-zero timeout does not turn into a non-blocking poll.
+fn timeout_secs(t: Duration) -> c_int {
+    let secs = t.as_secs() + if t.subsec_nanos() > 0 { 1 } else { 0 };
+    if secs > c_int::MAX as u64 { c_int::MAX } else { secs as c_int }
+}
+
+
 macro_rules! accessor_dispatch {
     ((String $( $ids:ident )+)) => {
         accessor_string! { $( $ids )+ }
     };
+    ((Strings $( $ids:ident )+)) => {
+        accessor_strings! { $( $ids )+ }
+    };
+    ((CompileJob $( $ids:ident )+)) => {
+        accessor_job! { $( $ids )+ }
+    };
+    ((Environments $( $ids:ident )+)) => {
+        accessor_environments! { $( $ids )+ }
+    };
+    ((Bytes $( $ids:ident )+)) => {
+        accessor_bytes! { $( $ids )+ }
+    };
     (($tname:ident $( $ids:ident )+)) => {
         accessor_simple! { ($tname) $( $ids )+ }
     };
@@ -258,140 +323,777 @@ macro_rules! accessors {
 }
 
 
+// File chunks may be large, so only their size is printed.
+macro_rules! fmt_field {
+    (Bytes $value:expr) => {
+        format_args!("<{} bytes>", $value.len())
+    };
+    ($kind:ident $value:expr) => {
+        format_args!("{:?}", $value)
+    };
+}
+
+// Messages are printed by converting them to their data structs, so that
+// all the fields are shown regardless of the backend.
+macro_rules! implement_fmt {
+    ($($name:path => $data:ident { $(($kind:ident $field:ident $get:ident $set:ident))* })+) => {
+        $(
+            impl ::std::fmt::Debug for $name {
+                fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                    match <$crate::data::$data as ::std::convert::TryFrom<&$name>>::try_from(self) {
+                        #[allow(unused_variables)]
+                        Ok(data) => f.debug_struct(stringify!($name))
+                            $( .field(stringify!($field), &fmt_field!($kind data.$field)) )*
+                            .finish(),
+                        Err(e) => write!(f, "{} {{ <{}> }}", stringify!($name), e),
+                    }
+                }
+            }
+
+            impl ::std::fmt::Display for $name {
+                #[allow(unused_variables)]
+                fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                    let name = short_type_name(stringify!($name));
+                    let data = match <$crate::data::$data as ::std::convert::TryFrom<&$name>>::try_from(self) {
+                        Ok(data) => data,
+                        Err(e) => return write!(f, "{} <{}>", name, e),
+                    };
+                    f.write_str(name)?;
+                    $( write!(f, " {}={}", stringify!($field), fmt_field!($kind data.$field))?; )*
+                    Ok(())
+                }
+            }
+        )+
+    }
+}
+
+fn short_type_name(path: &str) -> &str {
+    path.rsplit(':').next().unwrap_or(path).trim()
+}
+
+
+// JobDone and MonitorJobDone carry the same fields.
+macro_rules! job_done_methods {
+    () => {
+        pub fn new(job_id: u32, exit_code: i32) -> Self {
+            Self::try_new(job_id, exit_code).unwrap()
+        }
+
+        pub fn try_new(job_id: u32, exit_code: i32) -> Result<Self> {
+            let mut msg = Self::alloc()?;
+            msg.set_job_id(job_id);
+            msg.set_exit_code(exit_code);
+            Ok(msg)
+        }
+
+        pub fn stats(&self) -> JobStats {
+            JobStats {
+                real_msec: self.real_msec(),
+                user_msec: self.user_msec(),
+                sys_msec: self.sys_msec(),
+                page_faults: self.page_faults(),
+                in_compressed: self.in_compressed(),
+                in_uncompressed: self.in_uncompressed(),
+                out_compressed: self.out_compressed(),
+                out_uncompressed: self.out_uncompressed(),
+            }
+        }
+
+        pub fn set_stats(&mut self, stats: &JobStats) {
+            self.set_real_msec(stats.real_msec);
+            self.set_user_msec(stats.user_msec);
+            self.set_sys_msec(stats.sys_msec);
+            self.set_page_faults(stats.page_faults);
+            self.set_in_compressed(stats.in_compressed);
+            self.set_in_uncompressed(stats.in_uncompressed);
+            self.set_out_compressed(stats.out_compressed);
+            self.set_out_uncompressed(stats.out_uncompressed);
+        }
+
+        accessors! {
+            (u32
+                job_id msg_job_done_id
+                set_job_id msg_job_done_set_id)
+            (i32
+                exit_code msg_job_done_exit_code
+                set_exit_code msg_job_done_set_exit_code)
+            (u32
+                real_msec msg_job_done_real_msec
+                set_real_msec msg_job_done_set_real_msec)
+            (u32
+                user_msec msg_job_done_user_msec
+                set_user_msec msg_job_done_set_user_msec)
+            (u32
+                sys_msec msg_job_done_sys_msec
+                set_sys_msec msg_job_done_set_sys_msec)
+            (u32
+                page_faults msg_job_done_page_faults
+                set_page_faults msg_job_done_set_page_faults)
+            (u32
+                in_compressed msg_job_done_in_compressed
+                set_in_compressed msg_job_done_set_in_compressed)
+            (u32
+                in_uncompressed msg_job_done_in_uncompressed
+                set_in_uncompressed msg_job_done_set_in_uncompressed)
+            (u32
+                out_compressed msg_job_done_out_compressed
+                set_out_compressed msg_job_done_set_out_compressed)
+            (u32
+                out_uncompressed msg_job_done_out_uncompressed
+                set_out_uncompressed msg_job_done_set_out_uncompressed)
+            (u32
+                flags msg_job_done_flags
+                set_flags msg_job_done_set_flags)
+        }
+    }
+}
+
+
 pub mod msg {
     use super::*;
 
-    pub(super) trait Base: AsPtr {
-        fn as_raw_ptr(&self) -> *mut sys::Msg {
-            self.as_ptr() as *mut sys::Msg
+    implement_messages!{
+        Ping(PingData) => PingMsg(msg_ping_new) {
+            pub fn new() -> Self {
+                Self::try_new().unwrap()
+            }
+
+            pub fn try_new() -> Result<Self> {
+                Ping::alloc()
+            }
         }
 
-        fn as_const_ptr(&self) -> *const Self::Output {
-            self.as_raw_ptr() as *const Self::Output
+        End(EndData) => EndMsg(msg_end_new) {
+            pub fn new() -> Self {
+                Self::try_new().unwrap()
+            }
+
+            pub fn try_new() -> Result<Self> {
+                End::alloc()
+            }
         }
 
-        fn fill_from_channel(&mut self, chan: &mut MessageChannel) {
-            unsafe { sys::msg_fill_from_channel(self.as_raw_ptr(), chan.mc.as_ptr()) }
+        GetNativeEnv(GetNativeEnvData) => GetNativeEnvMsg(msg_get_native_env_new) {
+            pub fn new<S: AsRef<str>>(compiler: &str, extra_files: &[S]) -> Self {
+                Self::try_new(compiler, extra_files).unwrap()
+            }
+
+            pub fn try_new<S: AsRef<str>>(compiler: &str, extra_files: &[S]) -> Result<Self> {
+                let mut msg = Self::alloc()?;
+                msg.try_set_compiler(compiler)?;
+                msg.try_set_extra_files(extra_files)?;
+                Ok(msg)
+            }
+
+            accessors! {
+                (String
+                    compiler try_compiler msg_get_native_env_compiler
+                    set_compiler try_set_compiler msg_get_native_env_set_compiler)
+                (Strings
+                    extra_files try_extra_files msg_get_native_env_extra_files
+                    set_extra_files try_set_extra_files msg_get_native_env_set_extra_files)
+            }
         }
 
-        fn send_to_channel(&self, chan: &mut MessageChannel) {
-            unsafe { sys::msg_send_to_channel(self.as_raw_ptr(), chan.mc.as_ptr()) }
+        // The reply to GetNativeEnv, which icecc calls UseNativeEnvMsg.
+        NativeEnv(NativeEnvData) => UseNativeEnvMsg(msg_use_native_env_new) {
+            pub fn new(native_version: &str) -> Self {
+                Self::try_new(native_version).unwrap()
+            }
+
+            pub fn try_new(native_version: &str) -> Result<Self> {
+                let mut msg = Self::alloc()?;
+                msg.try_set_native_version(native_version)?;
+                Ok(msg)
+            }
+
+            accessors! {
+                (String
+                    native_version try_native_version msg_use_native_env_native_version
+                    set_native_version try_set_native_version msg_use_native_env_set_native_version)
+            }
         }
-    }
 
-    macro_rules! implement_messages {
-        ($($name:ident => $sysname:ident { $( $rest:tt )* })+) => {
-            $(
-                pub struct $name {
-                    msg: Rc<ptr::Msg>,
-                }
+        GetCS(GetCSData) => GetCSMsg(msg_get_cs_new) {
+            // Environments are (platform, version) pairs. The remaining
+            // fields default to what icecc uses: a single job, any target,
+            // and no host preference.
+            pub fn new<S: AsRef<str>>(environments: &[(S, S)], filename: &str, language: Language) -> Self {
+                Self::try_new(environments, filename, language).unwrap()
+            }
 
-                impl Base for $name {}
+            pub fn try_new<S: AsRef<str>>(environments: &[(S, S)], filename: &str, language: Language) -> Result<Self> {
+                let mut msg = Self::alloc()?;
+                msg.try_set_environments(environments)?;
+                msg.try_set_filename(filename)?;
+                msg.set_language(language);
+                msg.set_count(1);
+                Ok(msg)
+            }
 
-                impl AsPtr for $name {
-                    type Output = sys::$sysname;
-                    fn as_ptr(&self) -> *mut Self::Output {
-                        self.msg.as_ptr() as *mut Self::Output
-                    }
-                }
+            accessors! {
+                (Environments
+                    environments try_environments msg_get_cs_environments
+                    set_environments try_set_environments msg_get_cs_set_environments)
+                (String
+                    filename try_filename msg_get_cs_filename
+                    set_filename try_set_filename msg_get_cs_set_filename)
+                (Language
+                    language msg_get_cs_language
+                    set_language msg_get_cs_set_language)
+                (u32
+                    count msg_get_cs_count
+                    set_count msg_get_cs_set_count)
+                (String
+                    target try_target msg_get_cs_target
+                    set_target try_set_target msg_get_cs_set_target)
+                (u32
+                    arg_flags msg_get_cs_arg_flags
+                    set_arg_flags msg_get_cs_set_arg_flags)
+                (u32
+                    client_id msg_get_cs_client_id
+                    set_client_id msg_get_cs_set_client_id)
+                (String
+                    preferred_host try_preferred_host msg_get_cs_preferred_host
+                    set_preferred_host try_set_preferred_host msg_get_cs_set_preferred_host)
+                (u32
+                    minimal_host_version msg_get_cs_minimal_host_version
+                    set_minimal_host_version msg_get_cs_set_minimal_host_version)
+                (u32
+                    required_features msg_get_cs_required_features
+                    set_required_features msg_get_cs_set_required_features)
+            }
+        }
 
-                impl fmt::Debug for $name {
-                    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                        write!(f, "msg::{}", stringify!($name))
-                    }
-                }
+        UseCS(UseCSData) => UseCSMsg(msg_use_cs_new) {
+            accessors! {
+                (String
+                    host_name try_host_name msg_use_cs_host_name
+                    set_host_name try_set_host_name msg_use_cs_set_host_name)
+                (String
+                    host_platform try_host_platform msg_use_cs_host_platform
+                    set_host_platform try_set_host_platform msg_use_cs_set_host_platform)
+                (u32
+                    port msg_use_cs_port
+                    set_port msg_use_cs_set_port)
+                (u32
+                    job_id msg_use_cs_job_id
+                    set_job_id msg_use_cs_set_job_id)
+                (bool
+                    got_env msg_use_cs_got_env
+                    set_got_env msg_use_cs_set_got_env)
+                (u32
+                    client_id msg_use_cs_client_id
+                    set_client_id msg_use_cs_set_client_id)
+                (u32
+                    matched_job_id msg_use_cs_matched_job_id
+                    set_matched_job_id msg_use_cs_set_matched_job_id)
+            }
+        }
 
-                impl From<ptr::Msg> for $name {
-                    fn from(msg: ptr::Msg) -> Self {
-                        Self {
-                            msg: Rc::new(msg)
-                        }
-                    }
-                }
+        CompileFile(CompileFileData) => CompileFileMsg(msg_compile_file_new) {
+            pub fn new(job: &CompileJob) -> Self {
+                Self::try_new(job).unwrap()
+            }
 
-                impl From<$name> for Message {
-                    fn from(msg: $name) -> Message {
-                        Message::$name(msg)
-                    }
-                }
+            pub fn try_new(job: &CompileJob) -> Result<Self> {
+                let mut msg = Self::alloc()?;
+                msg.try_set_job(job)?;
+                Ok(msg)
+            }
 
-                impl $name {
-                    fn from_raw_ptr(ptr: *mut <$name as AsPtr>::Output) -> Self {
-                        $name::from(ptr::Msg(ptr as *mut $crate::sys::Msg))
-                    }
+            accessors! {
+                (CompileJob
+                    job try_job msg_compile_file_job
+                    set_job try_set_job msg_compile_file_set_job)
+            }
+        }
+        FileChunk(FileChunkData) => FileChunkMsg(msg_file_chunk_new) {
+            pub fn new(data: &[u8]) -> Self {
+                Self::try_new(data).unwrap()
+            }
 
-                    $( $rest )*
-                }
-            )+
+            pub fn try_new(data: &[u8]) -> Result<Self> {
+                let mut msg = Self::alloc()?;
+                msg.set_data(data);
+                Ok(msg)
+            }
+
+            accessors! {
+                (Bytes
+                    data msg_file_chunk_data
+                    set_data msg_file_chunk_set_data)
+            }
         }
-    }
 
-    implement_messages!{
-        Ping => PingMsg {
+        CompileResult(CompileResultData) => CompileResultMsg(msg_compile_result_new) {
+            pub fn new(status: i32, stdout: &str, stderr: &str) -> Self {
+                Self::try_new(status, stdout, stderr).unwrap()
+            }
+
+            pub fn try_new(status: i32, stdout: &str, stderr: &str) -> Result<Self> {
+                let mut msg = Self::alloc()?;
+                msg.set_status(status);
+                msg.try_set_stdout(stdout)?;
+                msg.try_set_stderr(stderr)?;
+                Ok(msg)
+            }
+
+            accessors! {
+                (i32
+                    status msg_compile_result_status
+                    set_status msg_compile_result_set_status)
+                (String
+                    stdout try_stdout msg_compile_result_stdout
+                    set_stdout try_set_stdout msg_compile_result_set_stdout)
+                (String
+                    stderr try_stderr msg_compile_result_stderr
+                    set_stderr try_set_stderr msg_compile_result_set_stderr)
+                (bool
+                    was_out_of_memory msg_compile_result_was_out_of_memory
+                    set_was_out_of_memory msg_compile_result_set_was_out_of_memory)
+                (bool
+                    have_dwo_file msg_compile_result_have_dwo_file
+                    set_have_dwo_file msg_compile_result_set_have_dwo_file)
+            }
+        }
+
+        JobBegin(JobBeginData) => JobBeginMsg(msg_job_begin_new) {
+            pub fn new(job_id: u32, start_time: u32) -> Self {
+                Self::try_new(job_id, start_time).unwrap()
+            }
+
+            pub fn try_new(job_id: u32, start_time: u32) -> Result<Self> {
+                let mut msg = Self::alloc()?;
+                msg.set_job_id(job_id);
+                msg.set_start_time(start_time);
+                Ok(msg)
+            }
+
+            accessors! {
+                (u32
+                    job_id msg_job_begin_job_id
+                    set_job_id msg_job_begin_set_job_id)
+                (u32
+                    start_time msg_job_begin_start_time
+                    set_start_time msg_job_begin_set_start_time)
+                (u32
+                    client_count msg_job_begin_client_count
+                    set_client_count msg_job_begin_set_client_count)
+            }
+        }
+
+        JobDone(JobDoneData) => JobDoneMsg(msg_job_done_new) {
+            job_done_methods! {}
+        }
+
+        LocalJobBegin(LocalJobBeginData) => JobLocalBeginMsg(msg_job_local_begin_new) {
+            // The start time is set to the current time, as icecc does.
+            pub fn new(job_id: u32, output_file: &str) -> Self {
+                Self::try_new(job_id, output_file).unwrap()
+            }
+
+            pub fn try_new(job_id: u32, output_file: &str) -> Result<Self> {
+                let start_time = SystemTime::now().duration_since(UNIX_EPOCH)
+                    .map(|t| t.as_secs() as u32).unwrap_or(0);
+                Self::try_new_with_start_time(job_id, output_file, start_time)
+            }
+
+            // For jobs which did not start right now, e.g. when relaying them.
+            pub fn new_with_start_time(job_id: u32, output_file: &str, start_time: u32) -> Self {
+                Self::try_new_with_start_time(job_id, output_file, start_time).unwrap()
+            }
+
+            pub fn try_new_with_start_time(job_id: u32, output_file: &str, start_time: u32) -> Result<Self> {
+                let mut msg = Self::alloc()?;
+                msg.set_job_id(job_id);
+                msg.try_set_output_file(output_file)?;
+                msg.set_start_time(start_time);
+                Ok(msg)
+            }
+
+            accessors! {
+                (u32
+                    job_id msg_job_local_begin_job_id
+                    set_job_id msg_job_local_begin_set_job_id)
+                (String
+                    output_file try_output_file msg_job_local_begin_output_file
+                    set_output_file try_set_output_file msg_job_local_begin_set_output_file)
+                (u32
+                    start_time msg_job_local_begin_start_time
+                    set_start_time msg_job_local_begin_set_start_time)
+            }
+        }
+
+        LocalJobDone(LocalJobDoneData) => JobLocalDoneMsg(msg_job_local_done_new) {
+            pub fn new(job_id: u32) -> Self {
+                Self::try_new(job_id).unwrap()
+            }
+
+            pub fn try_new(job_id: u32) -> Result<Self> {
+                let mut msg = Self::alloc()?;
+                msg.set_job_id(job_id);
+                Ok(msg)
+            }
+
+            accessors! {
+                (u32
+                    job_id msg_job_local_done_job_id
+                    set_job_id msg_job_local_done_set_job_id)
+            }
+        }
+
+        Login(LoginData) => LoginMsg(msg_login_new) {
+            pub fn new(port: u32, node_name: &str, host_platform: &str) -> Self {
+                Self::try_new(port, node_name, host_platform).unwrap()
+            }
+
+            pub fn try_new(port: u32, node_name: &str, host_platform: &str) -> Result<Self> {
+                let mut msg = Self::alloc()?;
+                msg.set_port(port);
+                msg.try_set_node_name(node_name)?;
+                msg.try_set_host_platform(host_platform)?;
+                Ok(msg)
+            }
+
+            accessors! {
+                (u32
+                    port msg_login_port
+                    set_port msg_login_set_port)
+                (u32
+                    max_jobs msg_login_max_jobs
+                    set_max_jobs msg_login_set_max_jobs)
+                (Environments
+                    environments try_environments msg_login_environments
+                    set_environments try_set_environments msg_login_set_environments)
+                (String
+                    node_name try_node_name msg_login_node_name
+                    set_node_name try_set_node_name msg_login_set_node_name)
+                (String
+                    host_platform try_host_platform msg_login_host_platform
+                    set_host_platform try_set_host_platform msg_login_set_host_platform)
+                (bool
+                    chroot_possible msg_login_chroot_possible
+                    set_chroot_possible msg_login_set_chroot_possible)
+                (bool
+                    noremote msg_login_noremote
+                    set_noremote msg_login_set_noremote)
+                (u32
+                    supported_features msg_login_supported_features
+                    set_supported_features msg_login_set_supported_features)
+            }
+        }
+
+        ConfCS(ConfCSData) => ConfCSMsg(msg_conf_cs_new) {
+            pub fn new(max_scheduler_pong: u32, max_scheduler_ping: u32) -> Self {
+                Self::try_new(max_scheduler_pong, max_scheduler_ping).unwrap()
+            }
+
+            pub fn try_new(max_scheduler_pong: u32, max_scheduler_ping: u32) -> Result<Self> {
+                let mut msg = Self::alloc()?;
+                msg.set_max_scheduler_pong(max_scheduler_pong);
+                msg.set_max_scheduler_ping(max_scheduler_ping);
+                Ok(msg)
+            }
+
+            accessors! {
+                (u32
+                    max_scheduler_pong msg_conf_cs_max_scheduler_pong
+                    set_max_scheduler_pong msg_conf_cs_set_max_scheduler_pong)
+                (u32
+                    max_scheduler_ping msg_conf_cs_max_scheduler_ping
+                    set_max_scheduler_ping msg_conf_cs_set_max_scheduler_ping)
+            }
+        }
+
+        Stats(StatsData) => StatsMsg(msg_stats_new) {
+            pub fn new() -> Self {
+                Self::try_new().unwrap()
+            }
+
+            pub fn try_new() -> Result<Self> {
+                Stats::alloc()
+            }
+
+            // Samples the current load of the machine, the same way iceccd
+            // does it. Note that this takes a short while, as CPU usage is
+            // measured over a small interval.
+            pub fn from_system() -> Result<Self> {
+                let sample = load::sample()?;
+                let mut msg = Self::try_new()?;
+                msg.set_load(sample.load);
+                msg.set_niceload(sample.niceload);
+                msg.set_load_avg1(sample.load_avg1);
+                msg.set_load_avg5(sample.load_avg5);
+                msg.set_load_avg10(sample.load_avg10);
+                msg.set_free_mem(sample.free_mem);
+                Ok(msg)
+            }
+
+            accessors! {
+                (u32
+                    load msg_stats_load
+                    set_load msg_stats_set_load)
+                (u32
+                    niceload msg_stats_niceload
+                    set_niceload msg_stats_set_niceload)
+                (u32
+                    load_avg1 msg_stats_load_avg1
+                    set_load_avg1 msg_stats_set_load_avg1)
+                (u32
+                    load_avg5 msg_stats_load_avg5
+                    set_load_avg5 msg_stats_set_load_avg5)
+                (u32
+                    load_avg10 msg_stats_load_avg10
+                    set_load_avg10 msg_stats_set_load_avg10)
+                (u32
+                    free_mem msg_stats_free_mem
+                    set_free_mem msg_stats_set_free_mem)
+            }
+        }
+
+        EnvTransfer(EnvTransferData) => EnvTransferMsg(msg_env_transfer_new) {
+            pub fn new(name: &str, target: &str) -> Self {
+                Self::try_new(name, target).unwrap()
+            }
+
+            pub fn try_new(name: &str, target: &str) -> Result<Self> {
+                let mut msg = Self::alloc()?;
+                msg.try_set_name(name)?;
+                msg.try_set_target(target)?;
+                Ok(msg)
+            }
+
+            accessors! {
+                (String
+                    name try_name msg_env_transfer_name
+                    set_name try_set_name msg_env_transfer_set_name)
+                (String
+                    target try_target msg_env_transfer_target
+                    set_target try_set_target msg_env_transfer_set_target)
+            }
+        }
+
+        InternalStatus(InternalStatusData) => GetInternalStatusMsg(msg_get_internal_status_new) {
+            pub fn new() -> Self {
+                Self::try_new().unwrap()
+            }
+
+            pub fn try_new() -> Result<Self> {
+                InternalStatus::alloc()
+            }
+        }
+
+        MonitorLogin(MonitorLoginData) => MonLoginMsg(msg_mon_login_new) {
             pub fn new() -> Self {
-                Ping::from_raw_ptr(unsafe { sys::msg_ping_new() })
-            }
-        }
-
-        End => EndMsg {}
-        GetNativeEnv => GetNativeEnvMsg {}
-        NativeEnv => UseNativeEnvMsg {}
-        GetCS => UseCSMsg {}
-        UseCS => UseCSMsg {}
-        CompileFile => CompileFileMsg {}
-        FileChunk => FileChunkMsg {}
-        CompileResult => CompileResultMsg {}
-        JobBegin => JobBeginMsg {}
-        JobDone => JobDoneMsg {}
-        LocalJobBegin => JobLocalBeginMsg {}
-        LocalJobDone => JobLocalDoneMsg {}
-        Login => LoginMsg {}
-        ConfCS => ConfCSMsg {}
-        Stats => StatsMsg {}
-        EnvTransfer => EnvTransferMsg {}
-        InternalStatus => GetInternalStatusMsg {}
-        MonitorLogin => MonLoginMsg {}
-        MonitorGetCS => MonGetCSMsg {}
-        MonitorJobBegin => MonJobBeginMsg {}
-
-        MonitorJobDone => MonJobDoneMsg {
-            pub fn job_id(&self) -> u32 {
-                unsafe { sys::msg_job_done_id(self.as_ptr() as *mut sys::JobDoneMsg) }
-            }
-        }
-
-        MonitorLocalJobBegin => MonLocalJobBeginMsg {
+                Self::try_new().unwrap()
+            }
+
+            pub fn try_new() -> Result<Self> {
+                MonitorLogin::alloc()
+            }
+        }
+
+        // The client id is the host id of the daemon which submitted the
+        // job. The target platform is only sent by schedulers speaking
+        // protocols older than 29, and is empty otherwise.
+        MonitorGetCS(MonitorGetCSData) => MonGetCSMsg(msg_mon_get_cs_new) {
+            accessors! {
+                (u32
+                    job_id msg_mon_get_cs_job_id
+                    set_job_id msg_mon_get_cs_set_job_id)
+                (u32
+                    client_id msg_mon_get_cs_client_id
+                    set_client_id msg_mon_get_cs_set_client_id)
+                (String
+                    filename try_filename msg_mon_get_cs_filename
+                    set_filename try_set_filename msg_mon_get_cs_set_filename)
+                (Language
+                    language msg_mon_get_cs_language
+                    set_language msg_mon_get_cs_set_language)
+                (String
+                    target try_target msg_mon_get_cs_target
+                    set_target try_set_target msg_mon_get_cs_set_target)
+            }
+        }
+
+        MonitorJobBegin(MonitorJobBeginData) => MonJobBeginMsg(msg_mon_job_begin_new) {
+            accessors! {
+                (u32
+                    job_id msg_mon_job_begin_job_id
+                    set_job_id msg_mon_job_begin_set_job_id)
+                (u32
+                    host_id msg_mon_job_begin_host_id
+                    set_host_id msg_mon_job_begin_set_host_id)
+                (u32
+                    start_time msg_mon_job_begin_start_time
+                    set_start_time msg_mon_job_begin_set_start_time)
+            }
+        }
+
+
+        // MonJobDoneMsg adds no fields of its own to JobDoneMsg.
+        MonitorJobDone(MonitorJobDoneData) => JobDoneMsg(msg_mon_job_done_new) {
+            job_done_methods! {}
+        }
+
+        MonitorLocalJobBegin(MonitorLocalJobBeginData) => MonLocalJobBeginMsg(msg_mon_local_job_begin_new) {
             accessors! {
                 (u32
                     job_id msg_mon_local_job_begin_job_id
                     set_job_id msg_mon_local_job_begin_set_job_id)
                 (String
-                    filename msg_mon_local_job_begin_file
-                    set_filename msg_mon_local_job_begin_set_file)
+                    filename try_filename msg_mon_local_job_begin_file
+                    set_filename try_set_filename msg_mon_local_job_begin_set_file)
             }
         }
 
-        MonitorStats => MonStatsMsg {
+        MonitorStats(MonitorStatsData) => MonStatsMsg(msg_mon_stats_new) {
             accessors! {
                 (u32
                     host_id msg_mon_stats_host_id
                     set_host_id msg_mon_stats_set_host_id)
                 (String
-                    message msg_mon_stats_message
-                    set_message msg_mon_stats_set_message)
+                    message try_message msg_mon_stats_message
+                    set_message try_set_message msg_mon_stats_set_message)
             }
         }
 
-        Text => TextMsg {}
-        StatusText => StatusTextMsg {}
-        VerifyEnv => VerifyEnvMsg {}
-        VerifyEnvResult => VerifyEnvResultMsg {}
-        BlacklistHostEnv => BlacklistHostEnvMsg {}
+        Text(TextData) => TextMsg(msg_text_new) {
+            pub fn new(text: &str) -> Self {
+                Self::try_new(text).unwrap()
+            }
+
+            pub fn try_new(text: &str) -> Result<Self> {
+                let mut msg = Self::alloc()?;
+                msg.try_set_text(text)?;
+                Ok(msg)
+            }
+
+            accessors! {
+                (String
+                    text try_text msg_text_text
+                    set_text try_set_text msg_text_set_text)
+            }
+        }
+        StatusText(StatusTextData) => StatusTextMsg(msg_status_text_new) {
+            pub fn new(text: &str) -> Self {
+                Self::try_new(text).unwrap()
+            }
+
+            pub fn try_new(text: &str) -> Result<Self> {
+                let mut msg = Self::alloc()?;
+                msg.try_set_text(text)?;
+                Ok(msg)
+            }
+
+            accessors! {
+                (String
+                    text try_text msg_status_text_text
+                    set_text try_set_text msg_status_text_set_text)
+            }
+        }
+        VerifyEnv(VerifyEnvData) => VerifyEnvMsg(msg_verify_env_new) {
+            pub fn new(target: &str, environment: &str) -> Self {
+                Self::try_new(target, environment).unwrap()
+            }
+
+            pub fn try_new(target: &str, environment: &str) -> Result<Self> {
+                let mut msg = Self::alloc()?;
+                msg.try_set_target(target)?;
+                msg.try_set_environment(environment)?;
+                Ok(msg)
+            }
+
+            accessors! {
+                (String
+                    target try_target msg_verify_env_target
+                    set_target try_set_target msg_verify_env_set_target)
+                (String
+                    environment try_environment msg_verify_env_environment
+                    set_environment try_set_environment msg_verify_env_set_environment)
+            }
+        }
+
+        VerifyEnvResult(VerifyEnvResultData) => VerifyEnvResultMsg(msg_verify_env_result_new) {
+            pub fn new(ok: bool) -> Self {
+                Self::try_new(ok).unwrap()
+            }
+
+            pub fn try_new(ok: bool) -> Result<Self> {
+                let mut msg = Self::alloc()?;
+                msg.set_ok(ok);
+                Ok(msg)
+            }
+
+            accessors! {
+                (bool
+                    ok msg_verify_env_result_ok
+                    set_ok msg_verify_env_result_set_ok)
+            }
+        }
+        BlacklistHostEnv(BlacklistHostEnvData) => BlacklistHostEnvMsg(msg_blacklist_host_env_new) {
+            pub fn new(host_name: &str, target: &str, environment: &str) -> Self {
+                Self::try_new(host_name, target, environment).unwrap()
+            }
+
+            pub fn try_new(host_name: &str, target: &str, environment: &str) -> Result<Self> {
+                let mut msg = Self::alloc()?;
+                msg.try_set_host_name(host_name)?;
+                msg.try_set_target(target)?;
+                msg.try_set_environment(environment)?;
+                Ok(msg)
+            }
+
+            accessors! {
+                (String
+                    host_name try_host_name msg_blacklist_host_env_host_name
+                    set_host_name try_set_host_name msg_blacklist_host_env_set_host_name)
+                (String
+                    target try_target msg_blacklist_host_env_target
+                    set_target try_set_target msg_blacklist_host_env_set_target)
+                (String
+                    environment try_environment msg_blacklist_host_env_environment
+                    set_environment try_set_environment msg_blacklist_host_env_set_environment)
+            }
+        }
+    }
+
+    impl Default for Ping {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Default for InternalStatus {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Default for MonitorLogin {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Default for Stats {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Default for End {
+        fn default() -> Self {
+            Self::new()
+        }
     }
 }
 
 
+// With serde, messages are tagged with the name of their variant.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Message {
     Ping(msg::Ping),
     End(msg::End),
@@ -425,81 +1127,43 @@ pub enum Message {
 }
 
 
-impl Message {
-    fn from_raw_ptr(ptr: *mut sys::Msg) -> Self {
-        let msg = ptr::Msg(ptr);
-        match msg.message_type() {
-            sys::MsgType::M_UNKNOWN => panic!("M_UNKNOWN messages are unused"),
-            sys::MsgType::M_PING => Message::Ping(msg.into()),
-            sys::MsgType::M_END => Message::End(msg.into()),
-            sys::MsgType::M_TIMEOUT => panic!("M_TIMEOUT messages are unused"),
-            sys::MsgType::M_GET_NATIVE_ENV => Message::GetNativeEnv(msg.into()),
-            sys::MsgType::M_NATIVE_ENV => Message::NativeEnv(msg.into()),
-            sys::MsgType::M_GET_CS => Message::GetCS(msg.into()),
-            sys::MsgType::M_USE_CS => Message::UseCS(msg.into()),
-            sys::MsgType::M_COMPILE_FILE => Message::CompileFile(msg.into()),
-            sys::MsgType::M_FILE_CHUNK => Message::FileChunk(msg.into()),
-            sys::MsgType::M_COMPILE_RESULT => Message::CompileResult(msg.into()),
-            sys::MsgType::M_JOB_BEGIN => Message::JobBegin(msg.into()),
-            sys::MsgType::M_JOB_DONE => Message::JobDone(msg.into()),
-            sys::MsgType::M_JOB_LOCAL_BEGIN => Message::LocalJobBegin(msg.into()),
-            sys::MsgType::M_JOB_LOCAL_DONE => Message::LocalJobDone(msg.into()),
-            sys::MsgType::M_LOGIN => Message::Login(msg.into()),
-            sys::MsgType::M_CS_CONF => Message::ConfCS(msg.into()),
-            sys::MsgType::M_STATS => Message::Stats(msg.into()),
-            sys::MsgType::M_TRANSFER_ENV => Message::EnvTransfer(msg.into()),
-            sys::MsgType::M_GET_INTERNALS => Message::InternalStatus(msg.into()),
-            sys::MsgType::M_MON_LOGIN => Message::MonitorLogin(msg.into()),
-            sys::MsgType::M_MON_GET_CS => Message::MonitorGetCS(msg.into()),
-            sys::MsgType::M_MON_JOB_BEGIN => Message::MonitorJobBegin(msg.into()),
-            sys::MsgType::M_MON_JOB_DONE => Message::MonitorJobDone(msg.into()),
-            sys::MsgType::M_MON_LOCAL_JOB_BEGIN => Message::MonitorLocalJobBegin(msg.into()),
-            sys::MsgType::M_MON_STATS => Message::MonitorStats(msg.into()),
-            sys::MsgType::M_TEXT => Message::Text(msg.into()),
-            sys::MsgType::M_STATUS_TEXT => Message::StatusText(msg.into()),
-            sys::MsgType::M_VERIFY_ENV => Message::VerifyEnv(msg.into()),
-            sys::MsgType::M_VERIFY_ENV_RESULT => Message::VerifyEnvResult(msg.into()),
-            sys::MsgType::M_BLACKLIST_HOST_ENV => Message::BlacklistHostEnv(msg.into()),
-        }
-    }
-
-    fn as_raw_ptr(&self) -> *mut sys::Msg {
-        use msg::Base;
+impl fmt::Display for Message
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Message::Ping(ref m) => m.as_raw_ptr(),
-            Message::End(ref m) => m.as_raw_ptr(),
-            Message::GetNativeEnv(ref m) => m.as_raw_ptr(),
-            Message::NativeEnv(ref m) => m.as_raw_ptr(),
-            Message::GetCS(ref m) => m.as_raw_ptr(),
-            Message::UseCS(ref m) => m.as_raw_ptr(),
-            Message::CompileFile(ref m) => m.as_raw_ptr(),
-            Message::FileChunk(ref m) => m.as_raw_ptr(),
-            Message::CompileResult(ref m) => m.as_raw_ptr(),
-            Message::JobBegin(ref m) => m.as_raw_ptr(),
-            Message::JobDone(ref m) => m.as_raw_ptr(),
-            Message::LocalJobBegin(ref m) => m.as_raw_ptr(),
-            Message::LocalJobDone(ref m) => m.as_raw_ptr(),
-            Message::Login(ref m) => m.as_raw_ptr(),
-            Message::ConfCS(ref m) => m.as_raw_ptr(),
-            Message::Stats(ref m) => m.as_raw_ptr(),
-            Message::EnvTransfer(ref m) => m.as_raw_ptr(),
-            Message::InternalStatus(ref m) => m.as_raw_ptr(),
-            Message::MonitorLogin(ref m) => m.as_raw_ptr(),
-            Message::MonitorGetCS(ref m) => m.as_raw_ptr(),
-            Message::MonitorJobBegin(ref m) => m.as_raw_ptr(),
-            Message::MonitorJobDone(ref m) => m.as_raw_ptr(),
-            Message::MonitorLocalJobBegin(ref m) => m.as_raw_ptr(),
-            Message::MonitorStats(ref m) => m.as_raw_ptr(),
-            Message::Text(ref m) => m.as_raw_ptr(),
-            Message::StatusText(ref m) => m.as_raw_ptr(),
-            Message::VerifyEnv(ref m) => m.as_raw_ptr(),
-            Message::VerifyEnvResult(ref m) => m.as_raw_ptr(),
-            Message::BlacklistHostEnv(ref m) => m.as_raw_ptr(),
+            Message::Ping(ref m) => fmt::Display::fmt(m, f),
+            Message::End(ref m) => fmt::Display::fmt(m, f),
+            Message::GetNativeEnv(ref m) => fmt::Display::fmt(m, f),
+            Message::NativeEnv(ref m) => fmt::Display::fmt(m, f),
+            Message::GetCS(ref m) => fmt::Display::fmt(m, f),
+            Message::UseCS(ref m) => fmt::Display::fmt(m, f),
+            Message::CompileFile(ref m) => fmt::Display::fmt(m, f),
+            Message::FileChunk(ref m) => fmt::Display::fmt(m, f),
+            Message::CompileResult(ref m) => fmt::Display::fmt(m, f),
+            Message::JobBegin(ref m) => fmt::Display::fmt(m, f),
+            Message::JobDone(ref m) => fmt::Display::fmt(m, f),
+            Message::LocalJobBegin(ref m) => fmt::Display::fmt(m, f),
+            Message::LocalJobDone(ref m) => fmt::Display::fmt(m, f),
+            Message::Login(ref m) => fmt::Display::fmt(m, f),
+            Message::ConfCS(ref m) => fmt::Display::fmt(m, f),
+            Message::Stats(ref m) => fmt::Display::fmt(m, f),
+            Message::EnvTransfer(ref m) => fmt::Display::fmt(m, f),
+            Message::InternalStatus(ref m) => fmt::Display::fmt(m, f),
+            Message::MonitorLogin(ref m) => fmt::Display::fmt(m, f),
+            Message::MonitorGetCS(ref m) => fmt::Display::fmt(m, f),
+            Message::MonitorJobBegin(ref m) => fmt::Display::fmt(m, f),
+            Message::MonitorJobDone(ref m) => fmt::Display::fmt(m, f),
+            Message::MonitorLocalJobBegin(ref m) => fmt::Display::fmt(m, f),
+            Message::MonitorStats(ref m) => fmt::Display::fmt(m, f),
+            Message::Text(ref m) => fmt::Display::fmt(m, f),
+            Message::StatusText(ref m) => fmt::Display::fmt(m, f),
+            Message::VerifyEnv(ref m) => fmt::Display::fmt(m, f),
+            Message::VerifyEnvResult(ref m) => fmt::Display::fmt(m, f),
+            Message::BlacklistHostEnv(ref m) => fmt::Display::fmt(m, f),
         }
     }
 }
 
-
 impl AsRef<Message> for Message {
     fn as_ref(&self) -> &Message {
         self
@@ -507,27 +1171,25 @@ impl AsRef<Message> for Message {
 }
 
 
-#[derive(Clone)]
 pub struct CompileJob {
-    cj: Rc<ptr::CompileJob>,
-}
-
-impl AsPtr for CompileJob {
-    type Output = sys::CompileJob;
-    fn as_ptr(&self) -> *mut sys::CompileJob {
-        self.cj.as_ptr()
-    }
+    #[cfg(not(feature = "pure-rust"))]
+    cj: ffi::ptr::CompileJob,
+    #[cfg(feature = "pure-rust")]
+    data: data::CompileJobData,
 }
 
 impl CompileJob
 {
-    fn from_raw_ptr(ptr: *mut sys::CompileJob) -> Self {
-        assert_ne!(ptr, 0 as *mut sys::CompileJob);
-        Self { cj: Rc::new(ptr::CompileJob(ptr)) }
+    pub fn new() -> Self {
+        Self::try_new().unwrap()
     }
 
-    pub fn new() -> Self {
-        Self::from_raw_ptr(unsafe { sys::compile_job_new() })
+    pub fn try_new() -> Result<Self> {
+        Self::alloc()
+    }
+
+    pub fn try_clone(&self) -> Result<Self> {
+        Self::try_from(data::CompileJobData::try_from(self)?)
     }
 
     accessors! {
@@ -538,19 +1200,316 @@ impl CompileJob
             language compile_job_language
             set_language compile_job_set_language)
         (String
-            compiler_name compile_job_compiler_name
-            set_compiler_name compile_job_set_compiler_name)
+            compiler_name try_compiler_name compile_job_compiler_name
+            set_compiler_name try_set_compiler_name compile_job_set_compiler_name)
         (String
-            environment_version compile_job_environment_version
-            set_environment_version compile_job_set_environment_version)
+            environment_version try_environment_version compile_job_environment_version
+            set_environment_version try_set_environment_version compile_job_set_environment_version)
         (String
-            input_file compile_job_input_file
-            set_input_file compile_job_set_input_file)
+            input_file try_input_file compile_job_input_file
+            set_input_file try_set_input_file compile_job_set_input_file)
         (String
-            output_file compile_job_output_file
-            set_output_file compile_job_set_output_file)
+            output_file try_output_file compile_job_output_file
+            set_output_file try_set_output_file compile_job_set_output_file)
         (String
-            target_platform compile_job_target_platform
-            set_target_platform compile_job_set_target_platform)
+            target_platform try_target_platform compile_job_target_platform
+            set_target_platform try_set_target_platform compile_job_set_target_platform)
+        (String
+            working_directory try_working_directory compile_job_working_directory
+            set_working_directory try_set_working_directory compile_job_set_working_directory)
+        (Strings
+            remote_flags try_remote_flags compile_job_remote_flags
+            set_remote_flags try_set_remote_flags compile_job_set_remote_flags)
+        (Strings
+            rest_flags try_rest_flags compile_job_rest_flags
+            set_rest_flags try_set_rest_flags compile_job_set_rest_flags)
+        (bool
+            dwarf_fission compile_job_dwarf_fission
+            set_dwarf_fission compile_job_set_dwarf_fission)
+    }
+}
+
+impl Default for CompileJob
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+// Resource usage and transfer sizes reported for a finished job. Sizes are
+// in bytes, "in" being the preprocessed source sent to the compile server
+// and "out" the resulting object file.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct JobStats {
+    pub real_msec: u32,
+    pub user_msec: u32,
+    pub sys_msec: u32,
+    pub page_faults: u32,
+    pub in_compressed: u32,
+    pub in_uncompressed: u32,
+    pub out_compressed: u32,
+    pub out_uncompressed: u32,
+}
+
+impl JobStats
+{
+    // Ratios of uncompressed to compressed size; None if nothing was sent.
+    pub fn in_compression_ratio(&self) -> Option<f64> {
+        ratio(self.in_uncompressed, self.in_compressed)
+    }
+
+    pub fn out_compression_ratio(&self) -> Option<f64> {
+        ratio(self.out_uncompressed, self.out_compressed)
+    }
+}
+
+fn ratio(uncompressed: u32, compressed: u32) -> Option<f64> {
+    if compressed == 0 { None } else { Some(uncompressed as f64 / compressed as f64) }
+}
+
+
+// Keepalive settings sent by the scheduler in ConfCS after a daemon logs
+// in. The daemon has to report its Stats at least every stats_interval,
+// and the scheduler drops daemons it has not heard from in link_timeout;
+// the daemon should consider the scheduler gone after that time as well.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SchedulerConfig {
+    pub stats_interval: Duration,
+    pub link_timeout: Duration,
+}
+
+impl SchedulerConfig
+{
+    pub fn stats_due(&self, last_stats: Instant) -> bool {
+        last_stats.elapsed() >= self.stats_interval
+    }
+
+    pub fn timed_out(&self, last_message: Instant) -> bool {
+        last_message.elapsed() >= self.link_timeout
+    }
+
+    // How long a daemon loop may wait for messages before it has to send
+    // Stats again.
+    pub fn next_stats(&self, last_stats: Instant) -> Duration {
+        self.stats_interval.checked_sub(last_stats.elapsed()).unwrap_or_default()
+    }
+}
+
+// Same values the scheduler uses, for daemons which have not received
+// a ConfCS yet.
+impl Default for SchedulerConfig
+{
+    fn default() -> Self {
+        Self {
+            stats_interval: Duration::from_secs(3),
+            link_timeout: Duration::from_secs(36),
+        }
+    }
+}
+
+impl<'a> From<&'a msg::ConfCS> for SchedulerConfig
+{
+    fn from(msg: &'a msg::ConfCS) -> Self {
+        Self {
+            stats_interval: Duration::from_secs(msg.max_scheduler_pong() as u64),
+            link_timeout: Duration::from_secs(msg.max_scheduler_ping() as u64),
+        }
+    }
+}
+
+
+implement_data! {
+    msg::Ping => PingData {}
+    msg::End => EndData {}
+
+    msg::GetNativeEnv => GetNativeEnvData {
+        (String compiler msg_get_native_env_compiler msg_get_native_env_set_compiler)
+        (Strings extra_files msg_get_native_env_extra_files msg_get_native_env_set_extra_files)
+    }
+
+    msg::NativeEnv => NativeEnvData {
+        (String native_version msg_use_native_env_native_version msg_use_native_env_set_native_version)
+    }
+
+    msg::GetCS => GetCSData {
+        (Environments environments msg_get_cs_environments msg_get_cs_set_environments)
+        (String filename msg_get_cs_filename msg_get_cs_set_filename)
+        (Language language msg_get_cs_language msg_get_cs_set_language)
+        (u32 count msg_get_cs_count msg_get_cs_set_count)
+        (String target msg_get_cs_target msg_get_cs_set_target)
+        (u32 arg_flags msg_get_cs_arg_flags msg_get_cs_set_arg_flags)
+        (u32 client_id msg_get_cs_client_id msg_get_cs_set_client_id)
+        (String preferred_host msg_get_cs_preferred_host msg_get_cs_set_preferred_host)
+        (u32 minimal_host_version msg_get_cs_minimal_host_version msg_get_cs_set_minimal_host_version)
+        (u32 required_features msg_get_cs_required_features msg_get_cs_set_required_features)
+    }
+
+    msg::UseCS => UseCSData {
+        (u32 job_id msg_use_cs_job_id msg_use_cs_set_job_id)
+        (u32 port msg_use_cs_port msg_use_cs_set_port)
+        (String host_name msg_use_cs_host_name msg_use_cs_set_host_name)
+        (String host_platform msg_use_cs_host_platform msg_use_cs_set_host_platform)
+        (bool got_env msg_use_cs_got_env msg_use_cs_set_got_env)
+        (u32 client_id msg_use_cs_client_id msg_use_cs_set_client_id)
+        (u32 matched_job_id msg_use_cs_matched_job_id msg_use_cs_set_matched_job_id)
+    }
+
+    msg::CompileFile => CompileFileData {
+        (Job job msg_compile_file_job msg_compile_file_set_job)
+    }
+
+    msg::FileChunk => FileChunkData {
+        (Bytes data msg_file_chunk_data msg_file_chunk_set_data)
+    }
+
+    msg::CompileResult => CompileResultData {
+        (String stderr msg_compile_result_stderr msg_compile_result_set_stderr)
+        (String stdout msg_compile_result_stdout msg_compile_result_set_stdout)
+        (i32 status msg_compile_result_status msg_compile_result_set_status)
+        (bool was_out_of_memory msg_compile_result_was_out_of_memory msg_compile_result_set_was_out_of_memory)
+        (bool have_dwo_file msg_compile_result_have_dwo_file msg_compile_result_set_have_dwo_file)
+    }
+
+    msg::JobBegin => JobBeginData {
+        (u32 job_id msg_job_begin_job_id msg_job_begin_set_job_id)
+        (u32 start_time msg_job_begin_start_time msg_job_begin_set_start_time)
+        (u32 client_count msg_job_begin_client_count msg_job_begin_set_client_count)
+    }
+
+    msg::JobDone => JobDoneData {
+        (u32 job_id msg_job_done_id msg_job_done_set_id)
+        (i32 exit_code msg_job_done_exit_code msg_job_done_set_exit_code)
+        (u32 real_msec msg_job_done_real_msec msg_job_done_set_real_msec)
+        (u32 user_msec msg_job_done_user_msec msg_job_done_set_user_msec)
+        (u32 sys_msec msg_job_done_sys_msec msg_job_done_set_sys_msec)
+        (u32 page_faults msg_job_done_page_faults msg_job_done_set_page_faults)
+        (u32 in_compressed msg_job_done_in_compressed msg_job_done_set_in_compressed)
+        (u32 in_uncompressed msg_job_done_in_uncompressed msg_job_done_set_in_uncompressed)
+        (u32 out_compressed msg_job_done_out_compressed msg_job_done_set_out_compressed)
+        (u32 out_uncompressed msg_job_done_out_uncompressed msg_job_done_set_out_uncompressed)
+        (u32 flags msg_job_done_flags msg_job_done_set_flags)
+    }
+
+    msg::LocalJobBegin => LocalJobBeginData {
+        (u32 start_time msg_job_local_begin_start_time msg_job_local_begin_set_start_time)
+        (String output_file msg_job_local_begin_output_file msg_job_local_begin_set_output_file)
+        (u32 job_id msg_job_local_begin_job_id msg_job_local_begin_set_job_id)
+    }
+
+    msg::LocalJobDone => LocalJobDoneData {
+        (u32 job_id msg_job_local_done_job_id msg_job_local_done_set_job_id)
+    }
+
+    msg::Login => LoginData {
+        (u32 port msg_login_port msg_login_set_port)
+        (u32 max_jobs msg_login_max_jobs msg_login_set_max_jobs)
+        (Environments environments msg_login_environments msg_login_set_environments)
+        (String node_name msg_login_node_name msg_login_set_node_name)
+        (String host_platform msg_login_host_platform msg_login_set_host_platform)
+        (bool chroot_possible msg_login_chroot_possible msg_login_set_chroot_possible)
+        (bool noremote msg_login_noremote msg_login_set_noremote)
+        (u32 supported_features msg_login_supported_features msg_login_set_supported_features)
+    }
+
+    msg::ConfCS => ConfCSData {
+        (u32 max_scheduler_pong msg_conf_cs_max_scheduler_pong msg_conf_cs_set_max_scheduler_pong)
+        (u32 max_scheduler_ping msg_conf_cs_max_scheduler_ping msg_conf_cs_set_max_scheduler_ping)
+    }
+
+    msg::Stats => StatsData {
+        (u32 load msg_stats_load msg_stats_set_load)
+        (u32 niceload msg_stats_niceload msg_stats_set_niceload)
+        (u32 load_avg1 msg_stats_load_avg1 msg_stats_set_load_avg1)
+        (u32 load_avg5 msg_stats_load_avg5 msg_stats_set_load_avg5)
+        (u32 load_avg10 msg_stats_load_avg10 msg_stats_set_load_avg10)
+        (u32 free_mem msg_stats_free_mem msg_stats_set_free_mem)
+    }
+
+    msg::EnvTransfer => EnvTransferData {
+        (String name msg_env_transfer_name msg_env_transfer_set_name)
+        (String target msg_env_transfer_target msg_env_transfer_set_target)
+    }
+
+    msg::InternalStatus => InternalStatusData {}
+    msg::MonitorLogin => MonitorLoginData {}
+
+    msg::MonitorGetCS => MonitorGetCSData {
+        (String filename msg_mon_get_cs_filename msg_mon_get_cs_set_filename)
+        (Language language msg_mon_get_cs_language msg_mon_get_cs_set_language)
+        (u32 job_id msg_mon_get_cs_job_id msg_mon_get_cs_set_job_id)
+        (u32 client_id msg_mon_get_cs_client_id msg_mon_get_cs_set_client_id)
+        (String target msg_mon_get_cs_target msg_mon_get_cs_set_target)
+    }
+
+    msg::MonitorJobBegin => MonitorJobBeginData {
+        (u32 job_id msg_mon_job_begin_job_id msg_mon_job_begin_set_job_id)
+        (u32 start_time msg_mon_job_begin_start_time msg_mon_job_begin_set_start_time)
+        (u32 host_id msg_mon_job_begin_host_id msg_mon_job_begin_set_host_id)
+    }
+
+    msg::MonitorJobDone => MonitorJobDoneData {
+        (u32 job_id msg_job_done_id msg_job_done_set_id)
+        (i32 exit_code msg_job_done_exit_code msg_job_done_set_exit_code)
+        (u32 real_msec msg_job_done_real_msec msg_job_done_set_real_msec)
+        (u32 user_msec msg_job_done_user_msec msg_job_done_set_user_msec)
+        (u32 sys_msec msg_job_done_sys_msec msg_job_done_set_sys_msec)
+        (u32 page_faults msg_job_done_page_faults msg_job_done_set_page_faults)
+        (u32 in_compressed msg_job_done_in_compressed msg_job_done_set_in_compressed)
+        (u32 in_uncompressed msg_job_done_in_uncompressed msg_job_done_set_in_uncompressed)
+        (u32 out_compressed msg_job_done_out_compressed msg_job_done_set_out_compressed)
+        (u32 out_uncompressed msg_job_done_out_uncompressed msg_job_done_set_out_uncompressed)
+        (u32 flags msg_job_done_flags msg_job_done_set_flags)
+    }
+
+    msg::MonitorLocalJobBegin => MonitorLocalJobBeginData {
+        (u32 host_id msg_mon_local_job_begin_host_id msg_mon_local_job_begin_set_host_id)
+        (u32 job_id msg_mon_local_job_begin_job_id msg_mon_local_job_begin_set_job_id)
+        (u32 start_time msg_mon_local_job_begin_start_time msg_mon_local_job_begin_set_start_time)
+        (String filename msg_mon_local_job_begin_file msg_mon_local_job_begin_set_file)
+    }
+
+    msg::MonitorStats => MonitorStatsData {
+        (u32 host_id msg_mon_stats_host_id msg_mon_stats_set_host_id)
+        (String message msg_mon_stats_message msg_mon_stats_set_message)
+    }
+
+    msg::Text => TextData {
+        (String text msg_text_text msg_text_set_text)
+    }
+
+    msg::StatusText => StatusTextData {
+        (String text msg_status_text_text msg_status_text_set_text)
+    }
+
+    msg::VerifyEnv => VerifyEnvData {
+        (String environment msg_verify_env_environment msg_verify_env_set_environment)
+        (String target msg_verify_env_target msg_verify_env_set_target)
+    }
+
+    msg::VerifyEnvResult => VerifyEnvResultData {
+        (bool ok msg_verify_env_result_ok msg_verify_env_result_set_ok)
+    }
+
+    msg::BlacklistHostEnv => BlacklistHostEnvData {
+        (String environment msg_blacklist_host_env_environment msg_blacklist_host_env_set_environment)
+        (String target msg_blacklist_host_env_target msg_blacklist_host_env_set_target)
+        (String host_name msg_blacklist_host_env_host_name msg_blacklist_host_env_set_host_name)
+    }
+
+    CompileJob => CompileJobData {
+        (u32 job_id compile_job_id compile_job_set_id)
+        (Language language compile_job_language compile_job_set_language)
+        (String compiler_name compile_job_compiler_name compile_job_set_compiler_name)
+        (String environment_version compile_job_environment_version compile_job_set_environment_version)
+        (String input_file compile_job_input_file compile_job_set_input_file)
+        (String output_file compile_job_output_file compile_job_set_output_file)
+        (String target_platform compile_job_target_platform compile_job_set_target_platform)
+        (String working_directory compile_job_working_directory compile_job_set_working_directory)
+        (Strings remote_flags compile_job_remote_flags compile_job_set_remote_flags)
+        (Strings rest_flags compile_job_rest_flags compile_job_set_rest_flags)
+        (bool dwarf_fission compile_job_dwarf_fission compile_job_set_dwarf_fission)
     }
 }
@@ -0,0 +1,181 @@
+//
+// load.rs
+// Copyright (C) 2017 Adrian Perez <aperez@igalia.com>
+// Distributed under terms of the MIT license.
+//
+
+use std::fs;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+
+// The counters in /proc/stat are cumulative since boot, so the CPU usage
+// is measured as the difference between two samples taken this far apart.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+
+// Loads are in the 0-1000 range, and free memory is in megabytes, which
+// is what iceccd reports to the scheduler.
+pub struct SystemLoad {
+    pub load: u32,
+    pub niceload: u32,
+    pub load_avg1: u32,
+    pub load_avg5: u32,
+    pub load_avg10: u32,
+    pub free_mem: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct CpuTimes {
+    nice: u64,
+    idle: u64,
+    total: u64,
+}
+
+fn invalid(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("cannot parse {}", path))
+}
+
+fn parse_cpu_times(contents: &str) -> io::Result<CpuTimes> {
+    let line = contents.lines().find(|l| l.starts_with("cpu ")).ok_or_else(|| invalid("/proc/stat"))?;
+    let ticks = line.split_whitespace().skip(1)
+        .map(str::parse::<u64>)
+        .collect::<::std::result::Result<Vec<_>, _>>()
+        .map_err(|_| invalid("/proc/stat"))?;
+    if ticks.len() < 4 {
+        return Err(invalid("/proc/stat"));
+    }
+    // Fields are user, nice, system, idle, and then (in newer kernels)
+    // iowait, which counts as idle time as well.
+    Ok(CpuTimes {
+        nice: ticks[1],
+        idle: ticks[3] + ticks.get(4).cloned().unwrap_or(0),
+        total: ticks.iter().take(8).sum(),
+    })
+}
+
+fn parse_load_averages(contents: &str) -> io::Result<(u32, u32, u32)> {
+    let mut avgs = contents.split_whitespace().map(|v| v.parse::<f64>().map(|v| (v * 1000.0) as u32));
+    match (avgs.next(), avgs.next(), avgs.next()) {
+        (Some(Ok(avg1)), Some(Ok(avg5)), Some(Ok(avg10))) => Ok((avg1, avg5, avg10)),
+        _ => Err(invalid("/proc/loadavg")),
+    }
+}
+
+// Returns the total and free memory, in kilobytes. Memory used by buffers
+// and caches counts as free, as the kernel reclaims it when needed.
+fn parse_memory(contents: &str) -> io::Result<(u64, u64)> {
+    let (mut total, mut free, mut available) = (None, 0, None);
+    for line in contents.lines() {
+        let mut fields = line.split_whitespace();
+        let (key, value) = match (fields.next(), fields.next().and_then(|v| v.parse::<u64>().ok())) {
+            (Some(key), Some(value)) => (key, value),
+            _ => continue,
+        };
+        match key {
+            "MemTotal:" => total = Some(value),
+            "MemAvailable:" => available = Some(value),
+            "MemFree:" | "Buffers:" | "Cached:" => free += value,
+            _ => (),
+        }
+    }
+    match total {
+        Some(total) if total > 0 => Ok((total, available.unwrap_or(free))),
+        _ => Err(invalid("/proc/meminfo")),
+    }
+}
+
+fn read_cpu_times() -> io::Result<CpuTimes> {
+    parse_cpu_times(&fs::read_to_string("/proc/stat")?)
+}
+
+pub fn sample() -> io::Result<SystemLoad> {
+    let before = read_cpu_times()?;
+    thread::sleep(SAMPLE_INTERVAL);
+    let after = read_cpu_times()?;
+    let (load_avg1, load_avg5, load_avg10) = parse_load_averages(&fs::read_to_string("/proc/loadavg")?)?;
+    let (mem_total, mem_free) = parse_memory(&fs::read_to_string("/proc/meminfo")?)?;
+
+    let total = after.total.saturating_sub(before.total);
+    let permille = |ticks: u64| (ticks.min(total) * 1000).checked_div(total).unwrap_or(0) as u32;
+    let idle = permille(after.idle.saturating_sub(before.idle));
+    let niceload = permille(after.nice.saturating_sub(before.nice));
+
+    // Same weighting of CPU usage and memory pressure as iceccd.
+    let memory_fill = 1000 - (mem_free.min(mem_total) * 1000 / mem_total) as u32;
+    Ok(SystemLoad {
+        load: (700 * (1000 - idle) + 300 * memory_fill) / 1000,
+        niceload,
+        load_avg1,
+        load_avg5,
+        load_avg10,
+        free_mem: (mem_free / 1024) as u32,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ CpuTimes, parse_cpu_times, parse_load_averages, parse_memory };
+
+    #[test]
+    fn cpu_times() {
+        let stat = "cpu  1000 20 300 4000 50 6 7 8 9 10\n\
+                    cpu0 500 10 150 2000 25 3 3 4 4 5\n\
+                    intr 12345 0 0\n";
+        // Guest time (the last two fields) is already part of user time.
+        assert_eq!(parse_cpu_times(stat).unwrap(), CpuTimes { nice: 20, idle: 4050, total: 5391 });
+    }
+
+    // Kernels before 2.5.41 only report user, nice, system and idle.
+    #[test]
+    fn cpu_times_old_kernel() {
+        assert_eq!(parse_cpu_times("cpu  100 20 30 400\n").unwrap(), CpuTimes { nice: 20, idle: 400, total: 550 });
+    }
+
+    #[test]
+    fn cpu_times_malformed() {
+        assert!(parse_cpu_times("").is_err());
+        assert!(parse_cpu_times("cpu0 1 2 3 4\n").is_err());
+        assert!(parse_cpu_times("cpu  1 2 3\n").is_err());
+        assert!(parse_cpu_times("cpu  1 2 x 4\n").is_err());
+    }
+
+    #[test]
+    fn load_averages() {
+        assert_eq!(parse_load_averages("0.52 1.25 2.00 2/345 6789\n").unwrap(), (520, 1250, 2000));
+        assert!(parse_load_averages("0.52 1.25\n").is_err());
+        assert!(parse_load_averages("").is_err());
+    }
+
+    #[test]
+    fn memory() {
+        let meminfo = "MemTotal:        8000000 kB\n\
+                       MemFree:         1000000 kB\n\
+                       MemAvailable:    5000000 kB\n\
+                       Buffers:          200000 kB\n\
+                       Cached:          2000000 kB\n\
+                       HugePages_Total:       0\n";
+        assert_eq!(parse_memory(meminfo).unwrap(), (8000000, 5000000));
+    }
+
+    // Without MemAvailable (before Linux 3.14) buffers and caches count as
+    // free memory.
+    #[test]
+    fn memory_without_available() {
+        let meminfo = "MemTotal:        8000000 kB\n\
+                       MemFree:         1000000 kB\n\
+                       Buffers:          200000 kB\n\
+                       Cached:          2000000 kB\n\
+                       SwapCached:            0 kB\n";
+        assert_eq!(parse_memory(meminfo).unwrap(), (8000000, 3200000));
+    }
+
+    #[test]
+    fn memory_malformed() {
+        assert!(parse_memory("").is_err());
+        assert!(parse_memory("MemFree: 1000 kB\n").is_err());
+        assert!(parse_memory("MemTotal: 0 kB\n").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+//
+// local.rs
+// Copyright (C) 2017 Adrian Perez <aperez@igalia.com>
+// Distributed under terms of the MIT license.
+//
+
+use std::env;
+use std::net::{ Ipv4Addr, SocketAddr };
+use std::path::{ Path, PathBuf };
+use std::time::Duration;
+
+use { Message, MessageChannel, Result, msg };
+
+
+const DAEMON_PORT: u16 = 10245;
+const DAEMON_CONNECT_TIMEOUT: Duration = Duration::from_secs(4);
+
+
+impl MessageChannel
+{
+    // Connects to the iceccd running on this machine, trying the same
+    // sockets as the icecc client, in the same order. ICECC_TEST_SOCKET
+    // may be set to use a different socket instead.
+    pub fn connect_local_daemon() -> Result<Self> {
+        if let Some(path) = env::var_os("ICECC_TEST_SOCKET") {
+            return Self::connect_unix(Path::new(&path));
+        }
+
+        let mut paths = vec![PathBuf::from("/var/run/icecc/iceccd.socket"),
+                             PathBuf::from("/var/run/iceccd.socket")];
+        if let Some(home) = env::var_os("HOME") {
+            paths.push(Path::new(&home).join(".iceccd.socket"));
+        }
+        for path in paths {
+            if let Ok(chan) = Self::connect_unix(&path) {
+                return Ok(chan);
+            }
+        }
+        Self::connect(&SocketAddr::from((Ipv4Addr::LOCALHOST, DAEMON_PORT)), DAEMON_CONNECT_TIMEOUT)
+    }
+}
+
+
+// Announces a job compiled on this machine to the local daemon, which
+// forwards it to the scheduler, so that it shows up in monitors and is
+// accounted for when scheduling. The end of the job is reported when the
+// guard is dropped, or explicitly with finish().
+pub struct LocalJobGuard {
+    channel: MessageChannel,
+    job_id: u32,
+    done: bool,
+}
+
+impl LocalJobGuard
+{
+    pub fn new(output_file: &str) -> Result<Self> {
+        Self::with_channel(MessageChannel::connect_local_daemon()?, output_file)
+    }
+
+    // The daemon identifies local jobs by the connection they come from,
+    // so the icecc client always uses zero as the job id.
+    pub fn with_channel(mut channel: MessageChannel, output_file: &str) -> Result<Self> {
+        let job_id = 0;
+        channel.try_send(Message::from(msg::LocalJobBegin::try_new(job_id, output_file)?))?;
+        Ok(Self { channel, job_id, done: false })
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.report_done()
+    }
+
+    fn report_done(&mut self) -> Result<()> {
+        self.done = true;
+        self.channel.try_send(Message::from(msg::LocalJobDone::try_new(self.job_id)?))
+    }
+}
+
+impl Drop for LocalJobGuard
+{
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.report_done();
+        }
+    }
+}
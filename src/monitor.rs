@@ -0,0 +1,96 @@
+//
+// monitor.rs
+// Copyright (C) 2017 Adrian Perez <aperez@igalia.com>
+// Distributed under terms of the MIT license.
+//
+
+use std::time::Duration;
+
+use { Error, Message, MessageChannel, Result, msg };
+
+
+// Monitors may go a long time without hearing from an idle scheduler,
+// so receiving is retried whenever this much time passes.
+const RECV_TIMEOUT: Duration = Duration::from_secs(60);
+
+
+#[derive(Debug)]
+pub enum MonitorEvent {
+    GetCS(msg::MonitorGetCS),
+    JobBegin(msg::MonitorJobBegin),
+    JobDone(msg::MonitorJobDone),
+    LocalJobBegin(msg::MonitorLocalJobBegin),
+    LocalJobDone(msg::LocalJobDone),
+    Stats(msg::MonitorStats),
+}
+
+impl MonitorEvent
+{
+    fn from_message(message: Message) -> Option<Self> {
+        Some(match message {
+            Message::MonitorGetCS(m) => MonitorEvent::GetCS(m),
+            Message::MonitorJobBegin(m) => MonitorEvent::JobBegin(m),
+            Message::MonitorJobDone(m) => MonitorEvent::JobDone(m),
+            Message::MonitorLocalJobBegin(m) => MonitorEvent::LocalJobBegin(m),
+            // The scheduler forwards these as sent by the daemons.
+            Message::LocalJobDone(m) => MonitorEvent::LocalJobDone(m),
+            Message::MonitorStats(m) => MonitorEvent::Stats(m),
+            _ => return None,
+        })
+    }
+}
+
+
+// Connection to a scheduler which has accepted us as a monitor. Iterating
+// over the session blocks waiting for events, and ends when the scheduler
+// closes the connection; other kinds of messages are skipped.
+pub struct MonitorSession {
+    channel: MessageChannel,
+    done: bool,
+}
+
+impl MonitorSession
+{
+    pub fn channel(&mut self) -> &mut MessageChannel {
+        &mut self.channel
+    }
+
+    pub fn into_channel(self) -> MessageChannel {
+        self.channel
+    }
+}
+
+impl Iterator for MonitorSession
+{
+    type Item = Result<MonitorEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.channel.try_recv(Some(RECV_TIMEOUT)) {
+                Ok(message) => if let Some(event) = MonitorEvent::from_message(message) {
+                    return Some(Ok(event));
+                },
+                Err(Error::Timeout) | Err(Error::UnknownMessageType(_)) => (),
+                Err(Error::Eof) => self.done = true,
+                Err(e) => {
+                    self.done = self.channel.eof();
+                    return Some(Err(e));
+                },
+            }
+        }
+        None
+    }
+}
+
+
+impl MessageChannel
+{
+    // Logs into the scheduler as a monitor, after which it starts sending
+    // the state of every node in the network, and then updates as jobs
+    // are scheduled and finished.
+    pub fn login_as_monitor(mut self) -> Result<MonitorSession> {
+        self.bulk_transfer();
+        self.try_send(Message::from(msg::MonitorLogin::try_new()?))?;
+        Ok(MonitorSession { channel: self, done: false })
+    }
+}
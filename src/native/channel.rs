@@ -0,0 +1,443 @@
+//
+// native/channel.rs
+// Copyright (C) 2017 Adrian Perez <aperez@igalia.com>
+// Distributed under terms of the MIT license.
+//
+
+use std::cmp;
+use std::io::{ self, Read, Write };
+use std::net::{ SocketAddr, TcpStream };
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::{ Duration, Instant };
+use libc::{ self, c_int };
+
+use { Error, Message, Result };
+use super::{ MAX_MSG_SIZE, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, poll_fd };
+use super::wire::{ self, Writer };
+
+
+// libicecc waits this long for a message when no timeout is given.
+const DEFAULT_RECV_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How long a send waits for the peer to finish the version handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+// Each side starts by sending its protocol version, then answers with the
+// version both will use (the lower of the two), and finally checks that
+// the peer agreed on the same one.
+#[derive(Clone, Copy, PartialEq)]
+enum Handshake {
+    Announce,
+    Confirm(u32),
+    Done,
+}
+
+// The local daemon may be reached through a Unix socket as well.
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream
+{
+    fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref s) => s.set_nodelay(nodelay),
+            Stream::Unix(_) => Ok(()),
+        }
+    }
+
+    fn as_raw_fd(&self) -> c_int {
+        match *self {
+            Stream::Tcp(ref s) => s.as_raw_fd(),
+            Stream::Unix(ref s) => s.as_raw_fd(),
+        }
+    }
+}
+
+impl Read for Stream
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut s) => s.read(buf),
+            Stream::Unix(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut s) => s.write(buf),
+            Stream::Unix(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref mut s) => s.flush(),
+            Stream::Unix(ref mut s) => s.flush(),
+        }
+    }
+}
+
+pub struct MessageChannel {
+    stream: Stream,
+    inbuf: Vec<u8>,
+    outbuf: Vec<u8>,
+    handshake: Handshake,
+    protocol: u32,
+    eof: bool,
+    error: bool,
+}
+
+fn remaining_ms(deadline: Instant) -> c_int {
+    let now = Instant::now();
+    if now >= deadline {
+        return 0;
+    }
+    let left = deadline - now;
+    let ms = left.as_secs() * 1000 + (left.subsec_nanos() as u64).div_ceil(1_000_000);
+    cmp::min(ms, c_int::MAX as u64) as c_int
+}
+
+impl MessageChannel
+{
+    pub(crate) fn connect(addr: &SocketAddr, timeout: Duration) -> Result<Self> {
+        Self::from_tcp(TcpStream::connect_timeout(addr, timeout)?)
+    }
+
+    pub(crate) fn from_tcp(stream: TcpStream) -> Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Self::from_stream(Stream::Tcp(stream))
+    }
+
+    pub(crate) fn connect_unix(path: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(path)?;
+        stream.set_nonblocking(true)?;
+        Self::from_stream(Stream::Unix(stream))
+    }
+
+    fn from_stream(stream: Stream) -> Result<Self> {
+        let mut chan = Self {
+            stream,
+            inbuf: Vec::new(),
+            outbuf: PROTOCOL_VERSION.to_le_bytes().to_vec(),
+            handshake: Handshake::Announce,
+            protocol: 0,
+            eof: false,
+            error: false,
+        };
+        chan.poll_flush()?;
+        Ok(chan)
+    }
+
+    pub fn fd(&self) -> c_int {
+        self.stream.as_raw_fd()
+    }
+
+    pub fn bulk_transfer(&mut self) {
+        let _ = self.stream.set_nodelay(false);
+    }
+
+    // Reads everything available until the socket would block, so that
+    // callers waiting on readiness know there is nothing left to read.
+    pub fn read_a_bit(&mut self) -> bool {
+        let mut buf = [0u8; 8192];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    self.eof = true;
+                    break;
+                },
+                Ok(n) => self.inbuf.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(_) => {
+                    self.error = true;
+                    break;
+                },
+            }
+        }
+        if !self.error {
+            self.process_input();
+        }
+        // Handshake answers queued while processing the input.
+        if !self.error && !self.eof {
+            let _ = self.poll_flush();
+        }
+        !self.error
+    }
+
+    fn process_input(&mut self) {
+        while self.handshake != Handshake::Done && self.inbuf.len() >= 4 {
+            let version = u32::from_le_bytes([self.inbuf[0], self.inbuf[1],
+                                              self.inbuf[2], self.inbuf[3]]);
+            self.inbuf.drain(..4);
+            match self.handshake {
+                Handshake::Announce => {
+                    let protocol = cmp::min(version, PROTOCOL_VERSION);
+                    if protocol < MIN_PROTOCOL_VERSION {
+                        self.error = true;
+                        return;
+                    }
+                    self.outbuf.extend_from_slice(&protocol.to_le_bytes());
+                    self.handshake = Handshake::Confirm(protocol);
+                },
+                Handshake::Confirm(protocol) => {
+                    if version != protocol {
+                        self.error = true;
+                        return;
+                    }
+                    self.protocol = protocol;
+                    self.handshake = Handshake::Done;
+                },
+                Handshake::Done => unreachable!(),
+            }
+        }
+        if self.handshake == Handshake::Done && self.inbuf.len() >= 4
+            && self.message_len() > MAX_MSG_SIZE {
+            self.error = true;
+        }
+    }
+
+    fn message_len(&self) -> usize {
+        u32::from_be_bytes([self.inbuf[0], self.inbuf[1], self.inbuf[2], self.inbuf[3]]) as usize
+    }
+
+    pub fn eof(&self) -> bool {
+        (self.eof || self.error) && !self.has_message()
+    }
+
+    pub fn is_text_based(&self) -> bool {
+        false
+    }
+
+    pub fn has_message(&self) -> bool {
+        self.handshake == Handshake::Done && !self.error && self.inbuf.len() >= 4
+            && self.inbuf.len() - 4 >= self.message_len()
+    }
+
+    fn take_message(&mut self) -> Result<Message> {
+        let len = self.message_len();
+        let result = if len < 4 {
+            Err(Error::Protocol)
+        } else {
+            let frame = &self.inbuf[4..4 + len];
+            let code = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]);
+            wire::decode(code, &frame[4..], self.protocol)
+        };
+        self.inbuf.drain(..4 + len);
+        self.process_input();
+        result
+    }
+
+    pub fn try_recv(&mut self, timeout: Option<Duration>) -> Result<Message> {
+        let deadline = Instant::now() + timeout.unwrap_or(DEFAULT_RECV_TIMEOUT);
+        loop {
+            if self.has_message() {
+                return self.take_message();
+            }
+            if self.error {
+                return Err(Error::Protocol);
+            }
+            if self.eof {
+                return Err(Error::Eof);
+            }
+            let wait = remaining_ms(deadline);
+            if wait == 0 || !poll_fd(self.fd(), libc::POLLIN, wait)? {
+                if Instant::now() >= deadline {
+                    return Err(Error::Timeout);
+                }
+                continue;
+            }
+            self.read_a_bit();
+        }
+    }
+
+    // Advances the version handshake as far as possible without blocking,
+    // returning whether it has completed.
+    pub fn poll_handshake(&mut self) -> Result<bool> {
+        if self.handshake != Handshake::Done && !self.eof && !self.error {
+            self.read_a_bit();
+        }
+        self.poll_flush()?;
+        if self.handshake == Handshake::Done {
+            Ok(true)
+        } else if self.error {
+            Err(Error::Protocol)
+        } else if self.eof {
+            Err(Error::Eof)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn wait_handshake(&mut self) -> Result<()> {
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        while !self.poll_handshake()? {
+            let wait = remaining_ms(deadline);
+            if wait == 0 {
+                return Err(Error::Timeout);
+            }
+            let events = if self.has_pending_output() { libc::POLLIN | libc::POLLOUT } else { libc::POLLIN };
+            poll_fd(self.fd(), events, wait)?;
+        }
+        Ok(())
+    }
+
+    pub fn has_pending_output(&self) -> bool {
+        !self.outbuf.is_empty()
+    }
+
+    // Writes as much of the pending output as possible without blocking,
+    // returning whether all of it has been written.
+    pub fn poll_flush(&mut self) -> Result<bool> {
+        while !self.outbuf.is_empty() {
+            match self.stream.write(&self.outbuf) {
+                Ok(0) => {
+                    self.eof = true;
+                    return Err(Error::Eof);
+                },
+                Ok(n) => {
+                    self.outbuf.drain(..n);
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    self.error = true;
+                    return Err(Error::Io(e));
+                },
+            }
+        }
+        Ok(true)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        while !self.poll_flush()? {
+            poll_fd(self.fd(), libc::POLLOUT, -1)?;
+        }
+        Ok(())
+    }
+
+    // Encodes a message into the output buffer, to be written by later
+    // calls to poll_flush(). The handshake must have completed already.
+    pub fn start_send<M: AsRef<Message>>(&mut self, message: M) -> Result<()> {
+        if self.eof {
+            return Err(Error::Eof);
+        }
+        if self.handshake != Handshake::Done {
+            return Err(Error::Protocol);
+        }
+
+        let mut w = Writer::new();
+        // Placeholder for the length, filled in once the body is encoded.
+        w.u32(0);
+        wire::encode(message.as_ref(), &mut w, self.protocol);
+        let mut frame = w.into_inner();
+        let len = (frame.len() - 4) as u32;
+        frame[..4].copy_from_slice(&len.to_be_bytes());
+        self.outbuf.extend_from_slice(&frame);
+        Ok(())
+    }
+
+    pub fn try_send<M: AsRef<Message>>(&mut self, message: M) -> Result<()> {
+        if self.eof {
+            return Err(Error::Eof);
+        }
+        self.wait_handshake()?;
+        self.start_send(message)?;
+        self.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ Read, Write };
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use std::time::Duration;
+
+    use { Error, Message, msg };
+    use super::{ MessageChannel, Stream };
+    use super::super::PROTOCOL_VERSION;
+
+    fn channel(stream: UnixStream) -> MessageChannel {
+        stream.set_nonblocking(true).unwrap();
+        MessageChannel::from_stream(Stream::Unix(stream)).unwrap()
+    }
+
+    // Reads what a channel sent to a peer which is not a channel itself.
+    fn read_u32s(peer: &mut UnixStream, count: usize) -> Vec<u32> {
+        let mut buf = vec![0u8; count * 4];
+        peer.read_exact(&mut buf).unwrap();
+        buf.chunks(4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]])).collect()
+    }
+
+    #[test]
+    fn send_and_receive_file() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let (mut sender, mut receiver) = (channel(a), channel(b));
+        let data: Vec<u8> = (0..250 * 1024).map(|i| (i % 251) as u8).collect();
+        let expected = data.clone();
+
+        let thread = thread::spawn(move || sender.send_file(&data[..]).unwrap());
+        let mut received = Vec::new();
+        let len = receiver.recv_file(&mut received).unwrap();
+        assert_eq!(thread.join().unwrap(), expected.len() as u64);
+        assert_eq!(len, expected.len() as u64);
+        assert_eq!(received, expected);
+        assert_eq!(receiver.protocol, PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn negotiates_older_protocol() {
+        let (a, mut peer) = UnixStream::pair().unwrap();
+        let mut chan = channel(a);
+        peer.write_all(&29u32.to_le_bytes()).unwrap();
+        peer.write_all(&29u32.to_le_bytes()).unwrap();
+        chan.try_send(Message::from(msg::Ping::new())).unwrap();
+        assert_eq!(chan.protocol, 29);
+
+        // The handshake is little endian, unlike everything else.
+        let mut versions = [0u8; 8];
+        peer.read_exact(&mut versions).unwrap();
+        assert_eq!(versions[..4], PROTOCOL_VERSION.to_le_bytes());
+        assert_eq!(versions[4..], 29u32.to_le_bytes());
+        assert_eq!(read_u32s(&mut peer, 2), [4, 66]);
+
+        drop(peer);
+        assert!(matches!(chan.try_recv(Some(Duration::from_secs(1))), Err(Error::Eof)));
+    }
+
+    #[test]
+    fn rejects_unsupported_protocol() {
+        let (a, mut peer) = UnixStream::pair().unwrap();
+        let mut chan = channel(a);
+        peer.write_all(&20u32.to_le_bytes()).unwrap();
+        assert!(matches!(chan.try_send(Message::from(msg::Ping::new())), Err(Error::Protocol)));
+    }
+
+    #[test]
+    fn non_blocking_send() {
+        let (a, mut peer) = UnixStream::pair().unwrap();
+        let mut chan = channel(a);
+        assert!(!chan.poll_handshake().unwrap());
+        assert!(matches!(chan.start_send(Message::from(msg::Ping::new())), Err(Error::Protocol)));
+
+        peer.write_all(&PROTOCOL_VERSION.to_le_bytes()).unwrap();
+        peer.write_all(&PROTOCOL_VERSION.to_le_bytes()).unwrap();
+        assert!(chan.poll_handshake().unwrap());
+        chan.start_send(Message::from(msg::Ping::new())).unwrap();
+        assert!(chan.has_pending_output());
+        assert!(chan.poll_flush().unwrap());
+        assert!(!chan.has_pending_output());
+        assert_eq!(read_u32s(&mut peer, 4)[2..], [4, 66]);
+    }
+}
@@ -0,0 +1,369 @@
+//
+// native/discover.rs
+// Copyright (C) 2017 Adrian Perez <aperez@igalia.com>
+// Distributed under terms of the MIT license.
+//
+
+use std::env;
+use std::ffi::CStr;
+use std::io;
+use std::mem;
+use std::net::{ IpAddr, Ipv4Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket };
+use std::os::unix::io::{ AsRawFd, FromRawFd };
+use std::ptr;
+use std::time::{ Duration, Instant };
+use libc::{ self, c_int };
+
+use { Error, Result };
+use super::PROTOCOL_VERSION;
+use super::channel::MessageChannel;
+
+
+const SCHEDULER_PORT: u16 = 8765;
+const DEFAULT_NETNAME: &str = "ICECREAM";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Broadcasts are repeated periodically in case they get lost, and once a
+// scheduler answers the others get a bit more time to answer as well, so
+// that the best one can be picked.
+const BROADCAST_INTERVAL: Duration = Duration::from_secs(1);
+const ANSWER_GRACE: Duration = Duration::from_millis(300);
+
+// Answers carry the scheduler version and start time since protocol 29,
+// and only the network name before.
+const ANSWER_LEN: usize = 268;
+
+
+struct Candidate {
+    addr: IpAddr,
+    version: u32,
+    start_time: u64,
+}
+
+impl Candidate
+{
+    // Prefer the newest scheduler, and among those the one that has been
+    // running for the longest time.
+    fn better_than(&self, other: &Candidate) -> bool {
+        self.version > other.version
+            || (self.version == other.version && self.start_time < other.start_time)
+    }
+}
+
+// Connection to a scheduler which has been started, but may not have been
+// established yet.
+struct Connecting {
+    stream: TcpStream,
+    started: Instant,
+}
+
+impl Connecting
+{
+    fn start(addr: &SocketAddr) -> io::Result<Self> {
+        let (storage, len) = sockaddr(addr);
+        let family = match *addr {
+            SocketAddr::V4(_) => libc::AF_INET,
+            SocketAddr::V6(_) => libc::AF_INET6,
+        };
+        let fd = unsafe { libc::socket(family, libc::SOCK_STREAM, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // The stream owns the descriptor from now on, and closes it on errors.
+        let stream = unsafe { TcpStream::from_raw_fd(fd) };
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        stream.set_nonblocking(true)?;
+        let ret = unsafe { libc::connect(fd, &storage as *const _ as *const libc::sockaddr, len) };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EINPROGRESS) {
+                return Err(err);
+            }
+        }
+        Ok(Self { stream, started: Instant::now() })
+    }
+
+    // Checks whether the connection has been established, without waiting.
+    fn poll(&self, timeout: Duration) -> io::Result<bool> {
+        if let Some(err) = self.stream.take_error()? {
+            return Err(err);
+        }
+        match self.stream.peer_addr() {
+            Ok(_) => Ok(true),
+            Err(ref e) if e.kind() == io::ErrorKind::NotConnected => {
+                if self.started.elapsed() >= timeout {
+                    Err(io::Error::new(io::ErrorKind::TimedOut, "connection to scheduler timed out"))
+                } else {
+                    Ok(false)
+                }
+            },
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn sockaddr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match *addr {
+        SocketAddr::V4(ref a) => {
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = a.port().to_be();
+            sin.sin_addr.s_addr = u32::from(*a.ip()).to_be();
+            mem::size_of::<libc::sockaddr_in>()
+        },
+        SocketAddr::V6(ref a) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = a.port().to_be();
+            sin6.sin6_addr.s6_addr = a.ip().octets();
+            sin6.sin6_flowinfo = a.flowinfo();
+            sin6.sin6_scope_id = a.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        },
+    };
+    (storage, len as libc::socklen_t)
+}
+
+pub struct ScheduleDiscoverer {
+    netname: String,
+    timeout: Duration,
+    started: Instant,
+    scheduler: Option<SocketAddr>,
+    socket: Option<UdpSocket>,
+    last_broadcast: Option<Instant>,
+    first_answer: Option<Instant>,
+    candidates: Vec<Candidate>,
+    connecting: Option<Connecting>,
+    error: Option<Error>,
+}
+
+fn resolve(scheduler: &str) -> Result<SocketAddr> {
+    let addrs = if scheduler.contains(':') {
+        scheduler.to_socket_addrs()
+    } else {
+        (scheduler, SCHEDULER_PORT).to_socket_addrs()
+    };
+    addrs?.next().ok_or_else(|| Error::Io(io::Error::new(io::ErrorKind::NotFound, scheduler.to_owned())))
+}
+
+// Broadcast address of every interface that has one; loopback is included
+// so that a scheduler running on the same machine is always found.
+fn broadcast_addrs() -> Vec<Ipv4Addr> {
+    let mut addrs = vec![Ipv4Addr::new(127, 0, 0, 1)];
+    let mut ifap: *mut libc::ifaddrs = ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifap) } == 0 {
+        let mut ifa = ifap;
+        while !ifa.is_null() {
+            let entry = unsafe { &*ifa };
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            let bcast = entry.ifa_ifu;
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            let bcast = entry.ifa_dstaddr;
+            if entry.ifa_flags & libc::IFF_BROADCAST as u32 != 0 && !bcast.is_null()
+                && unsafe { (*bcast).sa_family } as c_int == libc::AF_INET {
+                let sin = unsafe { &*(bcast as *const libc::sockaddr_in) };
+                let addr = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+            }
+            ifa = entry.ifa_next;
+        }
+        unsafe { libc::freeifaddrs(ifap) };
+    }
+    if addrs.len() == 1 {
+        addrs.push(Ipv4Addr::new(255, 255, 255, 255));
+    }
+    addrs
+}
+
+fn parse_answer(buf: &[u8]) -> Option<(String, u32, u64)> {
+    let kind = *buf.first()? as u32;
+    let (version, start_time, name) = if kind == PROTOCOL_VERSION + 2 && buf.len() > 13 {
+        let mut version = [0u8; 4];
+        let mut start_time = [0u8; 8];
+        version.copy_from_slice(&buf[1..5]);
+        start_time.copy_from_slice(&buf[5..13]);
+        (u32::from_le_bytes(version), u64::from_le_bytes(start_time), &buf[13..])
+    } else if kind == PROTOCOL_VERSION + 1 && buf.len() > 1 {
+        (0, 0, &buf[1..])
+    } else {
+        return None;
+    };
+    let name = CStr::from_bytes_until_nul(name).ok()?.to_str().ok()?;
+    Some((name.to_owned(), version, start_time))
+}
+
+impl ScheduleDiscoverer
+{
+    pub fn try_new<'f, T: Into<Option<&'f String>>>(netname: T) -> Result<Self> {
+        Self::try_new_with_options(netname.into().map(String::as_str).unwrap_or(""), "", 0)
+    }
+
+    // As with libicecc, an empty netname or scheduler falls back to the
+    // defaults, and the ICECC_SCHEDULER environment variable may be used
+    // to bypass broadcast discovery altogether.
+    pub fn try_new_with_options(netname: &str, scheduler: &str, timeout: u32) -> Result<Self> {
+        let scheduler = match scheduler {
+            "" => env::var("ICECC_SCHEDULER").or_else(|_| env::var("USE_SCHEDULER")).unwrap_or_default(),
+            s => s.to_owned(),
+        };
+        let mut disco = Self {
+            netname: if netname.is_empty() { DEFAULT_NETNAME.to_owned() } else { netname.to_owned() },
+            timeout: if timeout == 0 { DEFAULT_TIMEOUT } else { Duration::from_secs(timeout as u64) },
+            started: Instant::now(),
+            scheduler: None,
+            socket: None,
+            last_broadcast: None,
+            first_answer: None,
+            candidates: Vec::new(),
+            connecting: None,
+            error: None,
+        };
+        if scheduler.is_empty() {
+            let socket = UdpSocket::bind((Ipv4Addr::new(0, 0, 0, 0), 0))?;
+            socket.set_broadcast(true)?;
+            socket.set_nonblocking(true)?;
+            disco.socket = Some(socket);
+            disco.broadcast();
+        } else {
+            disco.scheduler = Some(resolve(&scheduler)?);
+        }
+        Ok(disco)
+    }
+
+    fn broadcast(&mut self) {
+        if let Some(ref socket) = self.socket {
+            for addr in broadcast_addrs() {
+                let _ = socket.send_to(&[PROTOCOL_VERSION as u8], (addr, SCHEDULER_PORT));
+            }
+        }
+        self.last_broadcast = Some(Instant::now());
+    }
+
+    fn collect_answers(&mut self) {
+        let mut buf = [0u8; ANSWER_LEN];
+        while let Some(Ok((len, from))) = self.socket.as_ref().map(|s| s.recv_from(&mut buf)) {
+            let (name, version, start_time) = match parse_answer(&buf[..len]) {
+                Some(answer) => answer,
+                _ => continue,
+            };
+            if name != self.netname {
+                continue;
+            }
+            let candidate = Candidate { addr: from.ip(), version, start_time };
+            match self.candidates.iter().position(|c| c.addr == candidate.addr) {
+                Some(i) => self.candidates[i] = candidate,
+                None => self.candidates.push(candidate),
+            }
+            if self.first_answer.is_none() {
+                self.first_answer = Some(Instant::now());
+            }
+        }
+    }
+
+    // Discovery gives up once the timeout expires with no connection in
+    // progress and no scheduler left to try, or right away if the only
+    // scheduler to try was given explicitly and could not be connected to.
+    pub fn timed_out(&mut self) -> bool {
+        if self.connecting.is_some() {
+            false
+        } else if self.socket.is_none() {
+            self.scheduler.is_none()
+        } else {
+            self.started.elapsed() >= self.timeout && self.candidates.is_empty()
+        }
+    }
+
+    // Error from the last failed attempt to connect to a scheduler, which
+    // is more useful than a timeout to explain why discovery failed.
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
+    pub fn listen_fd(&self) -> c_int {
+        match (self.scheduler, self.socket.as_ref()) {
+            (None, Some(socket)) => socket.as_raw_fd(),
+            _ => -1,
+        }
+    }
+
+    pub fn connect_fd(&self) -> c_int {
+        match self.connecting {
+            Some(ref c) => c.stream.as_raw_fd(),
+            None => -1,
+        }
+    }
+
+    fn start_connect(&mut self, addr: &SocketAddr) {
+        match Connecting::start(addr) {
+            Ok(c) => self.connecting = Some(c),
+            Err(e) => self.error = Some(Error::Io(e)),
+        }
+    }
+
+    fn finish_connect(&mut self) -> Option<MessageChannel> {
+        let result = match self.connecting {
+            Some(ref c) => c.poll(self.timeout),
+            None => return None,
+        };
+        let stream = match result {
+            Ok(false) => return None,
+            Ok(true) => self.connecting.take().unwrap().stream,
+            Err(e) => {
+                self.connecting = None;
+                self.error = Some(Error::Io(e));
+                return None;
+            },
+        };
+        match MessageChannel::from_tcp(stream) {
+            Ok(chan) => {
+                self.socket = None;
+                self.error = None;
+                Some(chan)
+            },
+            Err(e) => {
+                self.error = Some(e);
+                None
+            },
+        }
+    }
+
+    // Candidates are kept until a connection succeeds, so that the next
+    // best one is tried if connecting to a scheduler fails.
+    fn take_best(&mut self) -> Option<Candidate> {
+        let mut best = 0;
+        for (i, c) in self.candidates.iter().enumerate().skip(1) {
+            if c.better_than(&self.candidates[best]) {
+                best = i;
+            }
+        }
+        if self.candidates.is_empty() { None } else { Some(self.candidates.swap_remove(best)) }
+    }
+
+    pub fn try_get_scheduler(&mut self) -> Option<MessageChannel> {
+        if let Some(addr) = self.scheduler.take() {
+            self.start_connect(&addr);
+        }
+        if self.connecting.is_some() || self.socket.is_none() {
+            return self.finish_connect();
+        }
+
+        self.collect_answers();
+        let ready = match self.first_answer {
+            Some(t) => t.elapsed() >= ANSWER_GRACE || self.started.elapsed() >= self.timeout,
+            None => false,
+        };
+        if ready {
+            if let Some(best) = self.take_best() {
+                self.start_connect(&SocketAddr::new(best.addr, SCHEDULER_PORT));
+                return self.finish_connect();
+            }
+        }
+        if self.last_broadcast.map(|t| t.elapsed() >= BROADCAST_INTERVAL).unwrap_or(true) {
+            self.broadcast();
+        }
+        None
+    }
+}
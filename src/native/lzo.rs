@@ -0,0 +1,213 @@
+//
+// native/lzo.rs
+// Copyright (C) 2017 Adrian Perez <aperez@igalia.com>
+// Distributed under terms of the MIT license.
+//
+
+// File chunks are compressed with LZO1X. Decompression handles the full
+// format; compression only emits literal runs, which every LZO1X decoder
+// accepts and which is good enough for the small amounts of data clients
+// typically send.
+
+const M2_MAX_OFFSET: usize = 0x0800;
+
+
+struct Input<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Input<'a>
+{
+    fn byte(&mut self) -> Option<usize> {
+        let b = *self.buf.get(self.pos)?;
+        self.pos += 1;
+        Some(b as usize)
+    }
+
+    fn le16(&mut self) -> Option<usize> {
+        let lo = self.byte()?;
+        let hi = self.byte()?;
+        Some(lo | hi << 8)
+    }
+
+    // Extended length: a run of zero bytes each adding 255, followed by a
+    // non-zero byte which is added to the base.
+    fn length(&mut self, base: usize) -> Option<usize> {
+        let mut len = 0;
+        loop {
+            match self.byte()? {
+                0 => len += 255,
+                b => return Some(len + base + b),
+            }
+        }
+    }
+
+    fn literals(&mut self, out: &mut Vec<u8>, len: usize, max: usize) -> Option<()> {
+        if self.buf.len() - self.pos < len || out.len() + len > max {
+            return None;
+        }
+        out.extend_from_slice(&self.buf[self.pos..self.pos + len]);
+        self.pos += len;
+        Some(())
+    }
+}
+
+fn copy_match(out: &mut Vec<u8>, distance: usize, len: usize, max: usize) -> Option<()> {
+    if distance == 0 || distance > out.len() || out.len() + len > max {
+        return None;
+    }
+    let start = out.len() - distance;
+    // Matches may overlap the bytes they produce, so copy one at a time.
+    for i in 0..len {
+        let b = out[start + i];
+        out.push(b);
+    }
+    Some(())
+}
+
+pub fn decompress(src: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut input = Input { buf: src, pos: 0 };
+    let mut out = Vec::with_capacity(len);
+    let mut state = 0;
+
+    if let Some(&first) = src.first() {
+        if first > 17 {
+            input.pos = 1;
+            let t = first as usize - 17;
+            input.literals(&mut out, t, len)?;
+            state = if t < 4 { t } else { 4 };
+        }
+    }
+
+    loop {
+        let t = input.byte()?;
+        let next;
+        if t < 16 {
+            if state == 0 {
+                let run = if t == 0 { input.length(15)? } else { t };
+                input.literals(&mut out, run + 3, len)?;
+                state = 4;
+                continue;
+            } else if state != 4 {
+                next = t & 3;
+                let distance = 1 + (t >> 2) + (input.byte()? << 2);
+                copy_match(&mut out, distance, 2, len)?;
+            } else {
+                next = t & 3;
+                let distance = 1 + M2_MAX_OFFSET + (t >> 2) + (input.byte()? << 2);
+                copy_match(&mut out, distance, 3, len)?;
+            }
+        } else if t >= 64 {
+            next = t & 3;
+            let distance = 1 + ((t >> 2) & 7) + (input.byte()? << 3);
+            copy_match(&mut out, distance, (t >> 5) + 1, len)?;
+        } else if t >= 32 {
+            let run = match t & 31 {
+                0 => input.length(31)?,
+                n => n,
+            };
+            let offset = input.le16()?;
+            next = offset & 3;
+            copy_match(&mut out, 1 + (offset >> 2), run + 2, len)?;
+        } else {
+            let run = match t & 7 {
+                0 => input.length(7)?,
+                n => n,
+            };
+            let offset = input.le16()?;
+            next = offset & 3;
+            let distance = ((t & 8) << 11) + (offset >> 2);
+            if distance == 0 {
+                // End of stream marker.
+                return if out.len() == len { Some(out) } else { None };
+            }
+            copy_match(&mut out, distance + 0x4000, run + 2, len)?;
+        }
+
+        input.literals(&mut out, next, len)?;
+        state = next;
+    }
+}
+
+pub fn compress(src: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(src.len() + src.len() / 255 + 8);
+    match src.len() {
+        0 => (),
+        n if n < 4 => out.push(17 + n as u8),
+        n if n <= 18 => out.push(n as u8 - 3),
+        n => {
+            out.push(0);
+            let mut rest = n - 18;
+            while rest > 255 {
+                out.push(0);
+                rest -= 255;
+            }
+            out.push(rest as u8);
+        },
+    }
+    out.extend_from_slice(src);
+    out.extend_from_slice(&[0x11, 0x00, 0x00]);
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ compress, decompress };
+
+    // Linear congruential generator, to get incompressible data without
+    // depending on a random number crate.
+    fn pseudo_random(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect()
+    }
+
+    // Lengths around the boundaries of the literal run encodings.
+    #[test]
+    fn round_trip() {
+        for &len in &[0, 3, 4, 18, 19, 273, 274, 100 * 1024] {
+            let data = pseudo_random(len, len as u32);
+            assert_eq!(decompress(&compress(&data), len), Some(data), "length {}", len);
+        }
+    }
+
+    #[test]
+    fn wrong_length() {
+        let data = pseudo_random(100, 1);
+        let compressed = compress(&data);
+        assert_eq!(decompress(&compressed, 99), None);
+        assert_eq!(decompress(&compressed, 101), None);
+    }
+
+    #[test]
+    fn overlapping_match() {
+        // Three literals, then a match copying six bytes from three back.
+        let compressed = [20, b'a', b'b', b'c', 168, 0, 0x11, 0, 0];
+        assert_eq!(decompress(&compressed, 9), Some(b"abcabcabc".to_vec()));
+        // The match cannot reach before the start of the output.
+        let compressed = [20, b'a', b'b', b'c', 172, 0, 0x11, 0, 0];
+        assert_eq!(decompress(&compressed, 9), None);
+    }
+
+    #[test]
+    fn truncated_input() {
+        let data = pseudo_random(300, 2);
+        let compressed = compress(&data);
+        for end in 0..compressed.len() {
+            assert_eq!(decompress(&compressed[..end], data.len()), None, "truncated at {}", end);
+        }
+    }
+
+    #[test]
+    fn garbage_input() {
+        for seed in 0..2000 {
+            let garbage = pseudo_random(64, seed);
+            let _ = decompress(&garbage, 256);
+            let _ = decompress(&garbage, 0);
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//
+// native/mod.rs
+// Copyright (C) 2017 Adrian Perez <aperez@igalia.com>
+// Distributed under terms of the MIT license.
+//
+
+use std::io;
+use libc::{ self, c_int };
+
+use super::{ CompileJob, Result, data };
+
+mod channel;
+mod discover;
+mod lzo;
+mod wire;
+
+pub use self::channel::MessageChannel;
+pub use self::discover::ScheduleDiscoverer;
+
+
+// Version of the icecream protocol spoken by this implementation, and the
+// oldest one it is still able to talk to.
+const PROTOCOL_VERSION: u32 = 39;
+const MIN_PROTOCOL_VERSION: u32 = 21;
+
+// Messages larger than this are considered garbage, as libicecc does.
+const MAX_MSG_SIZE: usize = 16 * 1024 * 1024;
+
+
+macro_rules! accessor_simple {
+    (($t:ty) $fget:ident $sysfget:ident $fset:ident $sysfset:ident) => {
+        pub fn $fget(&self) -> $t {
+            self.data.$fget
+        }
+        pub fn $fset(&mut self, value: $t) {
+            self.data.$fget = value;
+        }
+    }
+}
+
+macro_rules! accessor_string {
+    ($fget:ident $tryfget:ident $sysfget:ident $fset:ident $tryfset:ident $sysfset:ident) => {
+        pub fn $fget(&self) -> String {
+            self.$tryfget().unwrap()
+        }
+
+        pub fn $tryfget(&self) -> $crate::Result<String> {
+            Ok(self.data.$fget.clone())
+        }
+
+        pub fn $fset(&mut self, value: &str) {
+            self.$tryfset(value).unwrap()
+        }
+
+        // Strings go over the wire NUL-terminated, so the same values
+        // rejected by libicecc are rejected here.
+        pub fn $tryfset(&mut self, value: &str) -> $crate::Result<()> {
+            ::std::ffi::CString::new(value)?;
+            self.data.$fget = value.to_owned();
+            Ok(())
+        }
+    }
+}
+
+macro_rules! accessor_strings {
+    ($fget:ident $tryfget:ident $sysfget:ident $fset:ident $tryfset:ident $sysfset:ident) => {
+        pub fn $fget(&self) -> Vec<String> {
+            self.$tryfget().unwrap()
+        }
+
+        pub fn $tryfget(&self) -> $crate::Result<Vec<String>> {
+            Ok(self.data.$fget.clone())
+        }
+
+        pub fn $fset<S: AsRef<str>>(&mut self, value: &[S]) {
+            self.$tryfset(value).unwrap()
+        }
+
+        pub fn $tryfset<S: AsRef<str>>(&mut self, value: &[S]) -> $crate::Result<()> {
+            let mut list = Vec::with_capacity(value.len());
+            for s in value {
+                ::std::ffi::CString::new(s.as_ref())?;
+                list.push(s.as_ref().to_owned());
+            }
+            self.data.$fget = list;
+            Ok(())
+        }
+    }
+}
+
+macro_rules! accessor_environments {
+    ($fget:ident $tryfget:ident $sysfget:ident $fset:ident $tryfset:ident $sysfset:ident) => {
+        pub fn $fget(&self) -> Vec<(String, String)> {
+            self.$tryfget().unwrap()
+        }
+
+        pub fn $tryfget(&self) -> $crate::Result<Vec<(String, String)>> {
+            Ok(self.data.$fget.clone())
+        }
+
+        pub fn $fset<S: AsRef<str>>(&mut self, value: &[(S, S)]) {
+            self.$tryfset(value).unwrap()
+        }
+
+        pub fn $tryfset<S: AsRef<str>>(&mut self, value: &[(S, S)]) -> $crate::Result<()> {
+            let mut list = Vec::with_capacity(value.len());
+            for &(ref platform, ref version) in value {
+                ::std::ffi::CString::new(platform.as_ref())?;
+                ::std::ffi::CString::new(version.as_ref())?;
+                list.push((platform.as_ref().to_owned(), version.as_ref().to_owned()));
+            }
+            self.data.$fget = list;
+            Ok(())
+        }
+    }
+}
+
+macro_rules! accessor_job {
+    ($fget:ident $tryfget:ident $sysfget:ident $fset:ident $tryfset:ident $sysfset:ident) => {
+        pub fn $fget(&self) -> $crate::CompileJob {
+            self.$tryfget().unwrap()
+        }
+
+        pub fn $tryfget(&self) -> $crate::Result<$crate::CompileJob> {
+            Ok($crate::CompileJob::from_data(self.data.$fget.clone()))
+        }
+
+        pub fn $fset(&mut self, job: &$crate::CompileJob) {
+            self.$tryfset(job).unwrap()
+        }
+
+        pub fn $tryfset(&mut self, job: &$crate::CompileJob) -> $crate::Result<()> {
+            self.data.$fget = job.as_data().clone();
+            Ok(())
+        }
+    }
+}
+
+macro_rules! accessor_bytes {
+    ($fget:ident $sysfget:ident $fset:ident $sysfset:ident) => {
+        pub fn $fget(&self) -> &[u8] {
+            &self.data.$fget
+        }
+
+        pub fn $fset(&mut self, value: &[u8]) {
+            self.data.$fget = value.to_vec();
+        }
+    }
+}
+
+macro_rules! implement_messages {
+    ($($name:ident($data:ident) => $sysname:ident $(($sysnew:ident))* { $( $rest:tt )* })+) => {
+        $(
+            pub struct $name {
+                data: $crate::data::$data,
+            }
+
+            impl From<$name> for $crate::Message {
+                fn from(msg: $name) -> $crate::Message {
+                    $crate::Message::$name(msg)
+                }
+            }
+
+            impl $name {
+                #[allow(dead_code)]
+                pub(crate) fn alloc() -> $crate::Result<Self> {
+                    Ok(Self { data: Default::default() })
+                }
+
+                pub(crate) fn from_data(data: $crate::data::$data) -> Self {
+                    Self { data }
+                }
+
+                pub(crate) fn as_data(&self) -> &$crate::data::$data {
+                    &self.data
+                }
+
+                $( $rest )*
+            }
+        )+
+    }
+}
+
+macro_rules! implement_data {
+    ($($name:path => $data:ident { $( $fields:tt )* })+) => {
+        $(
+            impl<'a> ::std::convert::TryFrom<&'a $name> for $crate::data::$data {
+                type Error = $crate::Error;
+
+                fn try_from(m: &'a $name) -> $crate::Result<Self> {
+                    Ok(m.as_data().clone())
+                }
+            }
+
+            impl ::std::convert::TryFrom<$crate::data::$data> for $name {
+                type Error = $crate::Error;
+
+                fn try_from(data: $crate::data::$data) -> $crate::Result<Self> {
+                    Ok(<$name>::from_data(data))
+                }
+            }
+        )+
+
+        implement_fmt! { $( $name => $data { $( $fields )* } )+ }
+    }
+}
+
+
+impl CompileJob
+{
+    pub(crate) fn alloc() -> Result<Self> {
+        Ok(Self { data: Default::default() })
+    }
+
+    pub(crate) fn from_data(data: data::CompileJobData) -> Self {
+        Self { data }
+    }
+
+    pub(crate) fn as_data(&self) -> &data::CompileJobData {
+        &self.data
+    }
+}
+
+
+// Waits until the descriptor is ready for the given events, or the timeout
+// (in milliseconds, negative to wait forever) expires. Returns whether the
+// descriptor became ready.
+fn poll_fd(fd: c_int, events: libc::c_short, timeout: c_int) -> io::Result<bool> {
+    let mut pfd = libc::pollfd { fd, events, revents: 0 };
+    match unsafe { libc::poll(&mut pfd, 1, timeout) } {
+        n if n > 0 => Ok(true),
+        0 => Ok(false),
+        _ => {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted { Ok(false) } else { Err(err) }
+        },
+    }
+}
@@ -0,0 +1,1083 @@
+//
+// native/wire.rs
+// Copyright (C) 2017 Adrian Perez <aperez@igalia.com>
+// Distributed under terms of the MIT license.
+//
+
+use std::cmp;
+
+use data::*;
+use { Error, Language, Message, Result };
+use msg;
+use super::{ MAX_MSG_SIZE, lzo };
+
+
+pub const M_PING: u32 = 66;
+pub const M_END: u32 = 67;
+pub const M_TIMEOUT: u32 = 68;
+pub const M_GET_NATIVE_ENV: u32 = 69;
+pub const M_NATIVE_ENV: u32 = 70;
+pub const M_GET_CS: u32 = 71;
+pub const M_USE_CS: u32 = 72;
+pub const M_COMPILE_FILE: u32 = 73;
+pub const M_FILE_CHUNK: u32 = 74;
+pub const M_COMPILE_RESULT: u32 = 75;
+pub const M_JOB_BEGIN: u32 = 76;
+pub const M_JOB_DONE: u32 = 77;
+pub const M_JOB_LOCAL_BEGIN: u32 = 78;
+pub const M_JOB_LOCAL_DONE: u32 = 79;
+pub const M_LOGIN: u32 = 80;
+pub const M_STATS: u32 = 81;
+pub const M_MON_LOGIN: u32 = 82;
+pub const M_MON_GET_CS: u32 = 83;
+pub const M_MON_JOB_BEGIN: u32 = 84;
+pub const M_MON_JOB_DONE: u32 = 85;
+pub const M_MON_LOCAL_JOB_BEGIN: u32 = 86;
+pub const M_MON_STATS: u32 = 87;
+pub const M_TRANSFER_ENV: u32 = 88;
+pub const M_TEXT: u32 = 89;
+pub const M_STATUS_TEXT: u32 = 90;
+pub const M_GET_INTERNALS: u32 = 91;
+pub const M_CS_CONF: u32 = 92;
+pub const M_VERIFY_ENV: u32 = 93;
+pub const M_VERIFY_ENV_RESULT: u32 = 94;
+pub const M_BLACKLIST_HOST_ENV: u32 = 95;
+
+
+// Reads are lenient in the same way as libicecc: running past the end of
+// the message yields zeroes and empty strings, because older peers simply
+// do not send the fields added in later protocol versions.
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a>
+{
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.buf.len() - self.pos < len {
+            return None;
+        }
+        let data = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Some(data)
+    }
+
+    pub fn u32(&mut self) -> u32 {
+        match self.bytes(4) {
+            Some(b) => (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32,
+            None => {
+                self.pos = self.buf.len();
+                0
+            },
+        }
+    }
+
+    pub fn i32(&mut self) -> i32 {
+        self.u32() as i32
+    }
+
+    pub fn bool(&mut self) -> bool {
+        self.u32() != 0
+    }
+
+    pub fn language(&mut self) -> Language {
+        match self.u32() {
+            0 => Language::C,
+            1 => Language::CPlusPlus,
+            2 => Language::ObjectiveC,
+            _ => Language::Custom,
+        }
+    }
+
+    pub fn string(&mut self) -> Result<String> {
+        let len = self.u32() as usize;
+        let data = match self.bytes(len) {
+            Some(data) if len > 0 => data,
+            _ => return Ok(String::new()),
+        };
+        // The length includes the terminating NUL.
+        let data = match data.iter().position(|&b| b == 0) {
+            Some(nul) => &data[..nul],
+            None => data,
+        };
+        Ok(String::from_utf8(data.to_vec())?)
+    }
+
+    pub fn strings(&mut self) -> Result<Vec<String>> {
+        let count = self.u32();
+        let mut list = Vec::new();
+        for _ in 0..count {
+            if self.pos >= self.buf.len() {
+                return Err(Error::Protocol);
+            }
+            list.push(self.string()?);
+        }
+        Ok(list)
+    }
+
+    pub fn environments(&mut self) -> Result<Vec<(String, String)>> {
+        let count = self.u32();
+        let mut list = Vec::new();
+        for _ in 0..count {
+            if self.pos >= self.buf.len() {
+                return Err(Error::Protocol);
+            }
+            let platform = self.string()?;
+            let version = self.string()?;
+            list.push((platform, version));
+        }
+        Ok(list)
+    }
+
+    pub fn compressed(&mut self) -> Result<Vec<u8>> {
+        let uncompressed_len = self.u32() as usize;
+        let compressed_len = self.u32() as usize;
+        if uncompressed_len == 0 {
+            return Ok(Vec::new());
+        }
+        // The length comes from the peer, and is used to size the output.
+        if uncompressed_len > MAX_MSG_SIZE {
+            return Err(Error::Protocol);
+        }
+        match self.bytes(compressed_len) {
+            Some(data) => lzo::decompress(data, uncompressed_len).ok_or(Error::Protocol),
+            None => Err(Error::Protocol),
+        }
+    }
+}
+
+
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer
+{
+    pub fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8,
+                                     (value >> 8) as u8, value as u8]);
+    }
+
+    pub fn i32(&mut self, value: i32) {
+        self.u32(value as u32)
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u32(if value { 1 } else { 0 })
+    }
+
+    pub fn language(&mut self, value: Language) {
+        self.u32(match value {
+            Language::C => 0,
+            Language::CPlusPlus => 1,
+            Language::ObjectiveC => 2,
+            Language::Custom => 3,
+        })
+    }
+
+    pub fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32 + 1);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    pub fn strings(&mut self, list: &[String]) {
+        self.u32(list.len() as u32);
+        for s in list {
+            self.string(s);
+        }
+    }
+
+    pub fn environments(&mut self, list: &[(String, String)]) {
+        self.u32(list.len() as u32);
+        for (platform, version) in list {
+            self.string(platform);
+            self.string(version);
+        }
+    }
+
+    pub fn compressed(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            self.u32(0);
+            self.u32(0);
+            return;
+        }
+        let compressed = lzo::compress(data);
+        self.u32(data.len() as u32);
+        self.u32(compressed.len() as u32);
+        self.buf.extend_from_slice(&compressed);
+    }
+}
+
+
+pub trait Codec: Sized {
+    fn decode(r: &mut Reader, protocol: u32) -> Result<Self>;
+    fn encode(&self, w: &mut Writer, protocol: u32);
+}
+
+macro_rules! empty_codecs {
+    ($($name:ident)+) => {
+        $(
+            impl Codec for $name {
+                fn decode(_r: &mut Reader, _protocol: u32) -> Result<Self> {
+                    Ok($name)
+                }
+
+                fn encode(&self, _w: &mut Writer, _protocol: u32) {
+                }
+            }
+        )+
+    }
+}
+
+empty_codecs! { PingData EndData InternalStatusData MonitorLoginData }
+
+
+impl Codec for CompileJobData
+{
+    fn decode(r: &mut Reader, protocol: u32) -> Result<Self> {
+        let mut job = CompileJobData {
+            language: r.language(),
+            job_id: r.u32(),
+            remote_flags: r.strings()?,
+            rest_flags: r.strings()?,
+            environment_version: r.string()?,
+            target_platform: r.string()?,
+            .. Default::default()
+        };
+        if protocol >= 30 {
+            job.compiler_name = r.string()?;
+        }
+        if protocol >= 34 {
+            job.input_file = r.string()?;
+            job.working_directory = r.string()?;
+        }
+        if protocol >= 35 {
+            job.output_file = r.string()?;
+            job.dwarf_fission = r.bool();
+        }
+        Ok(job)
+    }
+
+    fn encode(&self, w: &mut Writer, protocol: u32) {
+        w.language(self.language);
+        w.u32(self.job_id);
+        w.strings(&self.remote_flags);
+        w.strings(&self.rest_flags);
+        w.string(&self.environment_version);
+        w.string(&self.target_platform);
+        if protocol >= 30 {
+            w.string(&self.compiler_name);
+        }
+        if protocol >= 34 {
+            w.string(&self.input_file);
+            w.string(&self.working_directory);
+        }
+        if protocol >= 35 {
+            w.string(&self.output_file);
+            w.bool(self.dwarf_fission);
+        }
+    }
+}
+
+impl Codec for GetNativeEnvData
+{
+    fn decode(r: &mut Reader, protocol: u32) -> Result<Self> {
+        let compiler = r.string()?;
+        let extra_files = if protocol >= 32 { r.strings()? } else { Vec::new() };
+        Ok(Self { compiler, extra_files })
+    }
+
+    fn encode(&self, w: &mut Writer, protocol: u32) {
+        w.string(&self.compiler);
+        if protocol >= 32 {
+            w.strings(&self.extra_files);
+        }
+    }
+}
+
+impl Codec for NativeEnvData
+{
+    fn decode(r: &mut Reader, _protocol: u32) -> Result<Self> {
+        Ok(Self { native_version: r.string()? })
+    }
+
+    fn encode(&self, w: &mut Writer, _protocol: u32) {
+        w.string(&self.native_version);
+    }
+}
+
+impl Codec for GetCSData
+{
+    fn decode(r: &mut Reader, protocol: u32) -> Result<Self> {
+        let mut data = GetCSData {
+            environments: r.environments()?,
+            filename: r.string()?,
+            language: r.language(),
+            count: r.u32(),
+            target: r.string()?,
+            arg_flags: r.u32(),
+            client_id: r.u32(),
+            .. Default::default()
+        };
+        if protocol >= 22 {
+            data.preferred_host = r.string()?;
+        }
+        if protocol >= 31 && r.bool() {
+            data.minimal_host_version = 31;
+        }
+        if protocol >= 34 {
+            data.minimal_host_version = cmp::max(data.minimal_host_version, r.u32());
+        }
+        if protocol >= 39 {
+            data.required_features = r.u32();
+        }
+        Ok(data)
+    }
+
+    fn encode(&self, w: &mut Writer, protocol: u32) {
+        w.environments(&self.environments);
+        w.string(&self.filename);
+        w.language(self.language);
+        w.u32(self.count);
+        w.string(&self.target);
+        w.u32(self.arg_flags);
+        w.u32(self.client_id);
+        if protocol >= 22 {
+            w.string(&self.preferred_host);
+        }
+        if protocol >= 31 {
+            w.bool(self.minimal_host_version >= 31);
+        }
+        if protocol >= 34 {
+            w.u32(self.minimal_host_version);
+        }
+        if protocol >= 39 {
+            w.u32(self.required_features);
+        }
+    }
+}
+
+impl Codec for UseCSData
+{
+    fn decode(r: &mut Reader, protocol: u32) -> Result<Self> {
+        let mut data = UseCSData {
+            job_id: r.u32(),
+            port: r.u32(),
+            host_name: r.string()?,
+            host_platform: r.string()?,
+            got_env: r.bool(),
+            client_id: r.u32(),
+            .. Default::default()
+        };
+        if protocol >= 28 {
+            data.matched_job_id = r.u32();
+        }
+        Ok(data)
+    }
+
+    fn encode(&self, w: &mut Writer, protocol: u32) {
+        w.u32(self.job_id);
+        w.u32(self.port);
+        w.string(&self.host_name);
+        w.string(&self.host_platform);
+        w.bool(self.got_env);
+        w.u32(self.client_id);
+        if protocol >= 28 {
+            w.u32(self.matched_job_id);
+        }
+    }
+}
+
+impl Codec for CompileFileData
+{
+    fn decode(r: &mut Reader, protocol: u32) -> Result<Self> {
+        Ok(Self { job: CompileJobData::decode(r, protocol)? })
+    }
+
+    fn encode(&self, w: &mut Writer, protocol: u32) {
+        self.job.encode(w, protocol);
+    }
+}
+
+impl Codec for FileChunkData
+{
+    fn decode(r: &mut Reader, _protocol: u32) -> Result<Self> {
+        Ok(Self { data: r.compressed()? })
+    }
+
+    fn encode(&self, w: &mut Writer, _protocol: u32) {
+        w.compressed(&self.data);
+    }
+}
+
+impl Codec for CompileResultData
+{
+    fn decode(r: &mut Reader, protocol: u32) -> Result<Self> {
+        let mut data = CompileResultData {
+            stderr: r.string()?,
+            stdout: r.string()?,
+            status: r.i32(),
+            was_out_of_memory: r.bool(),
+            .. Default::default()
+        };
+        if protocol >= 35 {
+            data.have_dwo_file = r.bool();
+        }
+        Ok(data)
+    }
+
+    fn encode(&self, w: &mut Writer, protocol: u32) {
+        w.string(&self.stderr);
+        w.string(&self.stdout);
+        w.i32(self.status);
+        w.bool(self.was_out_of_memory);
+        if protocol >= 35 {
+            w.bool(self.have_dwo_file);
+        }
+    }
+}
+
+impl Codec for JobBeginData
+{
+    fn decode(r: &mut Reader, protocol: u32) -> Result<Self> {
+        let job_id = r.u32();
+        let start_time = r.u32();
+        let client_count = if protocol >= 39 { r.u32() } else { 0 };
+        Ok(Self { job_id, start_time, client_count })
+    }
+
+    fn encode(&self, w: &mut Writer, protocol: u32) {
+        w.u32(self.job_id);
+        w.u32(self.start_time);
+        if protocol >= 39 {
+            w.u32(self.client_count);
+        }
+    }
+}
+
+impl Codec for JobDoneData
+{
+    fn decode(r: &mut Reader, _protocol: u32) -> Result<Self> {
+        Ok(Self {
+            job_id: r.u32(),
+            exit_code: r.i32(),
+            real_msec: r.u32(),
+            user_msec: r.u32(),
+            sys_msec: r.u32(),
+            page_faults: r.u32(),
+            in_compressed: r.u32(),
+            in_uncompressed: r.u32(),
+            out_compressed: r.u32(),
+            out_uncompressed: r.u32(),
+            flags: r.u32(),
+        })
+    }
+
+    fn encode(&self, w: &mut Writer, _protocol: u32) {
+        w.u32(self.job_id);
+        w.i32(self.exit_code);
+        w.u32(self.real_msec);
+        w.u32(self.user_msec);
+        w.u32(self.sys_msec);
+        w.u32(self.page_faults);
+        w.u32(self.in_compressed);
+        w.u32(self.in_uncompressed);
+        w.u32(self.out_compressed);
+        w.u32(self.out_uncompressed);
+        w.u32(self.flags);
+    }
+}
+
+impl Codec for LocalJobBeginData
+{
+    fn decode(r: &mut Reader, _protocol: u32) -> Result<Self> {
+        let start_time = r.u32();
+        let output_file = r.string()?;
+        let job_id = r.u32();
+        Ok(Self { start_time, output_file, job_id })
+    }
+
+    fn encode(&self, w: &mut Writer, _protocol: u32) {
+        w.u32(self.start_time);
+        w.string(&self.output_file);
+        w.u32(self.job_id);
+    }
+}
+
+impl Codec for LocalJobDoneData
+{
+    fn decode(r: &mut Reader, _protocol: u32) -> Result<Self> {
+        Ok(Self { job_id: r.u32() })
+    }
+
+    fn encode(&self, w: &mut Writer, _protocol: u32) {
+        w.u32(self.job_id);
+    }
+}
+
+impl Codec for LoginData
+{
+    fn decode(r: &mut Reader, protocol: u32) -> Result<Self> {
+        let mut data = LoginData {
+            port: r.u32(),
+            max_jobs: r.u32(),
+            environments: r.environments()?,
+            node_name: r.string()?,
+            host_platform: r.string()?,
+            chroot_possible: r.bool(),
+            .. Default::default()
+        };
+        if protocol >= 26 {
+            data.noremote = r.bool();
+        }
+        if protocol >= 39 {
+            data.supported_features = r.u32();
+        }
+        Ok(data)
+    }
+
+    fn encode(&self, w: &mut Writer, protocol: u32) {
+        w.u32(self.port);
+        w.u32(self.max_jobs);
+        w.environments(&self.environments);
+        w.string(&self.node_name);
+        w.string(&self.host_platform);
+        w.bool(self.chroot_possible);
+        if protocol >= 26 {
+            w.bool(self.noremote);
+        }
+        if protocol >= 39 {
+            w.u32(self.supported_features);
+        }
+    }
+}
+
+impl Codec for ConfCSData
+{
+    fn decode(r: &mut Reader, _protocol: u32) -> Result<Self> {
+        let max_scheduler_pong = r.u32();
+        let max_scheduler_ping = r.u32();
+        // Benchmark source, unused by the scheduler for a long time.
+        r.string()?;
+        Ok(Self { max_scheduler_pong, max_scheduler_ping })
+    }
+
+    fn encode(&self, w: &mut Writer, _protocol: u32) {
+        w.u32(self.max_scheduler_pong);
+        w.u32(self.max_scheduler_ping);
+        w.string("");
+    }
+}
+
+// The nice load is only used locally by iceccd, and never sent.
+impl Codec for StatsData
+{
+    fn decode(r: &mut Reader, _protocol: u32) -> Result<Self> {
+        Ok(Self {
+            load: r.u32(),
+            load_avg1: r.u32(),
+            load_avg5: r.u32(),
+            load_avg10: r.u32(),
+            free_mem: r.u32(),
+            .. Default::default()
+        })
+    }
+
+    fn encode(&self, w: &mut Writer, _protocol: u32) {
+        w.u32(self.load);
+        w.u32(self.load_avg1);
+        w.u32(self.load_avg5);
+        w.u32(self.load_avg10);
+        w.u32(self.free_mem);
+    }
+}
+
+impl Codec for EnvTransferData
+{
+    fn decode(r: &mut Reader, _protocol: u32) -> Result<Self> {
+        let name = r.string()?;
+        let target = r.string()?;
+        Ok(Self { name, target })
+    }
+
+    fn encode(&self, w: &mut Writer, _protocol: u32) {
+        w.string(&self.name);
+        w.string(&self.target);
+    }
+}
+
+impl Codec for MonitorGetCSData
+{
+    fn decode(r: &mut Reader, protocol: u32) -> Result<Self> {
+        let mut data = MonitorGetCSData::default();
+        if protocol >= 29 {
+            data.filename = r.string()?;
+            data.language = r.language();
+        } else {
+            let request = GetCSData::decode(r, protocol)?;
+            data.filename = request.filename;
+            data.language = request.language;
+            data.target = request.target;
+        }
+        data.job_id = r.u32();
+        data.client_id = r.u32();
+        Ok(data)
+    }
+
+    fn encode(&self, w: &mut Writer, protocol: u32) {
+        if protocol >= 29 {
+            w.string(&self.filename);
+            w.language(self.language);
+        } else {
+            let request = GetCSData {
+                filename: self.filename.clone(),
+                language: self.language,
+                count: 1,
+                target: self.target.clone(),
+                .. Default::default()
+            };
+            request.encode(w, protocol);
+        }
+        w.u32(self.job_id);
+        w.u32(self.client_id);
+    }
+}
+
+impl Codec for MonitorJobBeginData
+{
+    fn decode(r: &mut Reader, _protocol: u32) -> Result<Self> {
+        let job_id = r.u32();
+        let start_time = r.u32();
+        let host_id = r.u32();
+        Ok(Self { job_id, start_time, host_id })
+    }
+
+    fn encode(&self, w: &mut Writer, _protocol: u32) {
+        w.u32(self.job_id);
+        w.u32(self.start_time);
+        w.u32(self.host_id);
+    }
+}
+
+impl Codec for MonitorLocalJobBeginData
+{
+    fn decode(r: &mut Reader, _protocol: u32) -> Result<Self> {
+        let host_id = r.u32();
+        let job_id = r.u32();
+        let start_time = r.u32();
+        let filename = r.string()?;
+        Ok(Self { host_id, job_id, start_time, filename })
+    }
+
+    fn encode(&self, w: &mut Writer, _protocol: u32) {
+        w.u32(self.host_id);
+        w.u32(self.job_id);
+        w.u32(self.start_time);
+        w.string(&self.filename);
+    }
+}
+
+impl Codec for MonitorStatsData
+{
+    fn decode(r: &mut Reader, _protocol: u32) -> Result<Self> {
+        let host_id = r.u32();
+        let message = r.string()?;
+        Ok(Self { host_id, message })
+    }
+
+    fn encode(&self, w: &mut Writer, _protocol: u32) {
+        w.u32(self.host_id);
+        w.string(&self.message);
+    }
+}
+
+impl Codec for TextData
+{
+    fn decode(r: &mut Reader, _protocol: u32) -> Result<Self> {
+        Ok(Self { text: r.string()? })
+    }
+
+    fn encode(&self, w: &mut Writer, _protocol: u32) {
+        w.string(&self.text);
+    }
+}
+
+impl Codec for StatusTextData
+{
+    fn decode(r: &mut Reader, _protocol: u32) -> Result<Self> {
+        Ok(Self { text: r.string()? })
+    }
+
+    fn encode(&self, w: &mut Writer, _protocol: u32) {
+        w.string(&self.text);
+    }
+}
+
+impl Codec for VerifyEnvData
+{
+    fn decode(r: &mut Reader, _protocol: u32) -> Result<Self> {
+        let environment = r.string()?;
+        let target = r.string()?;
+        Ok(Self { environment, target })
+    }
+
+    fn encode(&self, w: &mut Writer, _protocol: u32) {
+        w.string(&self.environment);
+        w.string(&self.target);
+    }
+}
+
+impl Codec for VerifyEnvResultData
+{
+    fn decode(r: &mut Reader, _protocol: u32) -> Result<Self> {
+        Ok(Self { ok: r.bool() })
+    }
+
+    fn encode(&self, w: &mut Writer, _protocol: u32) {
+        w.bool(self.ok);
+    }
+}
+
+impl Codec for BlacklistHostEnvData
+{
+    fn decode(r: &mut Reader, _protocol: u32) -> Result<Self> {
+        let environment = r.string()?;
+        let target = r.string()?;
+        let host_name = r.string()?;
+        Ok(Self { environment, target, host_name })
+    }
+
+    fn encode(&self, w: &mut Writer, _protocol: u32) {
+        w.string(&self.environment);
+        w.string(&self.target);
+        w.string(&self.host_name);
+    }
+}
+
+
+macro_rules! message_codecs {
+    ($(($name:ident $code:ident $data:ident))+) => {
+        // Decodes a message body (everything after the type code).
+        pub fn decode(code: u32, payload: &[u8], protocol: u32) -> Result<Message> {
+            let mut r = Reader::new(payload);
+            match code {
+                $( $code => Ok(Message::$name(msg::$name::from_data($data::decode(&mut r, protocol)?))), )+
+                M_TIMEOUT => Err(Error::Timeout),
+                code => Err(Error::UnknownMessageType(code)),
+            }
+        }
+
+        // Encodes the type code followed by the message body.
+        pub fn encode(message: &Message, w: &mut Writer, protocol: u32) {
+            match *message {
+                $(
+                    Message::$name(ref m) => {
+                        w.u32($code);
+                        m.as_data().encode(w, protocol);
+                    },
+                )+
+            }
+        }
+    }
+}
+
+message_codecs! {
+    (Ping M_PING PingData)
+    (End M_END EndData)
+    (GetNativeEnv M_GET_NATIVE_ENV GetNativeEnvData)
+    (NativeEnv M_NATIVE_ENV NativeEnvData)
+    (GetCS M_GET_CS GetCSData)
+    (UseCS M_USE_CS UseCSData)
+    (CompileFile M_COMPILE_FILE CompileFileData)
+    (FileChunk M_FILE_CHUNK FileChunkData)
+    (CompileResult M_COMPILE_RESULT CompileResultData)
+    (JobBegin M_JOB_BEGIN JobBeginData)
+    (JobDone M_JOB_DONE JobDoneData)
+    (LocalJobBegin M_JOB_LOCAL_BEGIN LocalJobBeginData)
+    (LocalJobDone M_JOB_LOCAL_DONE LocalJobDoneData)
+    (Login M_LOGIN LoginData)
+    (ConfCS M_CS_CONF ConfCSData)
+    (Stats M_STATS StatsData)
+    (EnvTransfer M_TRANSFER_ENV EnvTransferData)
+    (InternalStatus M_GET_INTERNALS InternalStatusData)
+    (MonitorLogin M_MON_LOGIN MonitorLoginData)
+    (MonitorGetCS M_MON_GET_CS MonitorGetCSData)
+    (MonitorJobBegin M_MON_JOB_BEGIN MonitorJobBeginData)
+    (MonitorJobDone M_MON_JOB_DONE MonitorJobDoneData)
+    (MonitorLocalJobBegin M_MON_LOCAL_JOB_BEGIN MonitorLocalJobBeginData)
+    (MonitorStats M_MON_STATS MonitorStatsData)
+    (Text M_TEXT TextData)
+    (StatusText M_STATUS_TEXT StatusTextData)
+    (VerifyEnv M_VERIFY_ENV VerifyEnvData)
+    (VerifyEnvResult M_VERIFY_ENV_RESULT VerifyEnvResultData)
+    (BlacklistHostEnv M_BLACKLIST_HOST_ENV BlacklistHostEnvData)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use data::*;
+    use { Language, Message, msg };
+    use super::{ Codec, MAX_MSG_SIZE, Reader, Writer, decode, encode };
+
+    // Versions at which fields were added to messages.
+    const PROTOCOLS: [u32; 9] = [21, 22, 26, 28, 29, 31, 34, 35, 39];
+
+    fn strings(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn round_trip<T: Codec>(data: &T, protocol: u32) -> T {
+        let mut w = Writer::new();
+        data.encode(&mut w, protocol);
+        let buf = w.into_inner();
+        T::decode(&mut Reader::new(&buf), protocol).expect("decoding failed")
+    }
+
+    fn compile_job() -> CompileJobData {
+        CompileJobData {
+            job_id: 42,
+            language: Language::CPlusPlus,
+            compiler_name: "g++".to_owned(),
+            environment_version: "env.tar.gz".to_owned(),
+            input_file: "main.cpp".to_owned(),
+            output_file: "main.o".to_owned(),
+            target_platform: "x86_64".to_owned(),
+            working_directory: "/src".to_owned(),
+            remote_flags: strings(&["-O2", "-g"]),
+            rest_flags: strings(&["-DNDEBUG"]),
+            dwarf_fission: true,
+        }
+    }
+
+    fn get_cs() -> GetCSData {
+        GetCSData {
+            environments: vec![("x86_64".to_owned(), "env.tar.gz".to_owned())],
+            filename: "main.cpp".to_owned(),
+            language: Language::CPlusPlus,
+            count: 1,
+            target: "x86_64".to_owned(),
+            arg_flags: 3,
+            client_id: 7,
+            preferred_host: "builder".to_owned(),
+            minimal_host_version: 34,
+            required_features: 1,
+        }
+    }
+
+    fn login() -> LoginData {
+        LoginData {
+            port: 10245,
+            max_jobs: 8,
+            environments: vec![("x86_64".to_owned(), "env.tar.gz".to_owned())],
+            node_name: "builder".to_owned(),
+            host_platform: "x86_64".to_owned(),
+            chroot_possible: true,
+            noremote: true,
+            supported_features: 1,
+        }
+    }
+
+    // One message of each type, with every field set to something other
+    // than its default, and the fields only sent by old protocols unset.
+    fn messages() -> Vec<Message> {
+        macro_rules! messages {
+            ($($name:ident($data:expr))+) => {
+                vec![ $( Message::$name(msg::$name::from_data($data)), )+ ]
+            }
+        }
+        messages! {
+            Ping(PingData)
+            End(EndData)
+            GetNativeEnv(GetNativeEnvData { compiler: "gcc".to_owned(), extra_files: strings(&["a", "b"]) })
+            NativeEnv(NativeEnvData { native_version: "env.tar.gz".to_owned() })
+            GetCS(get_cs())
+            UseCS(UseCSData {
+                job_id: 1, port: 10245, host_name: "builder".to_owned(), host_platform: "x86_64".to_owned(),
+                got_env: true, client_id: 7, matched_job_id: 2,
+            })
+            CompileFile(CompileFileData { job: compile_job() })
+            FileChunk(FileChunkData { data: b"int main() { return 0; }".to_vec() })
+            CompileResult(CompileResultData {
+                stderr: "warning".to_owned(), stdout: "output".to_owned(), status: -1,
+                was_out_of_memory: true, have_dwo_file: true,
+            })
+            JobBegin(JobBeginData { job_id: 1, start_time: 1500000000, client_count: 3 })
+            JobDone(JobDoneData {
+                job_id: 1, exit_code: -2, real_msec: 3, user_msec: 4, sys_msec: 5, page_faults: 6,
+                in_compressed: 7, in_uncompressed: 8, out_compressed: 9, out_uncompressed: 10, flags: 1,
+            })
+            LocalJobBegin(LocalJobBeginData { start_time: 1500000000, output_file: "main.o".to_owned(), job_id: 1 })
+            LocalJobDone(LocalJobDoneData { job_id: 1 })
+            Login(login())
+            ConfCS(ConfCSData { max_scheduler_pong: 3, max_scheduler_ping: 36 })
+            Stats(StatsData { load: 500, niceload: 0, load_avg1: 1000, load_avg5: 2000, load_avg10: 3000, free_mem: 4096 })
+            EnvTransfer(EnvTransferData { name: "env.tar.gz".to_owned(), target: "x86_64".to_owned() })
+            InternalStatus(InternalStatusData)
+            MonitorLogin(MonitorLoginData)
+            MonitorGetCS(MonitorGetCSData {
+                filename: "main.cpp".to_owned(), language: Language::C, job_id: 1, client_id: 7, target: String::new(),
+            })
+            MonitorJobBegin(MonitorJobBeginData { job_id: 1, start_time: 1500000000, host_id: 2 })
+            MonitorJobDone(JobDoneData { job_id: 1, exit_code: 1, .. Default::default() })
+            MonitorLocalJobBegin(MonitorLocalJobBeginData {
+                host_id: 2, job_id: 1, start_time: 1500000000, filename: "main.cpp".to_owned(),
+            })
+            MonitorStats(MonitorStatsData { host_id: 2, message: "Name:builder\nLoad:500\n".to_owned() })
+            Text(TextData { text: "hello".to_owned() })
+            StatusText(StatusTextData { text: "status".to_owned() })
+            VerifyEnv(VerifyEnvData { environment: "env.tar.gz".to_owned(), target: "x86_64".to_owned() })
+            VerifyEnvResult(VerifyEnvResultData { ok: true })
+            BlacklistHostEnv(BlacklistHostEnvData {
+                environment: "env.tar.gz".to_owned(), target: "x86_64".to_owned(), host_name: "builder".to_owned(),
+            })
+        }
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        for &protocol in &PROTOCOLS {
+            for message in messages() {
+                let mut w = Writer::new();
+                encode(&message, &mut w, protocol);
+                let buf = w.into_inner();
+                let code = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+                let decoded = decode(code, &buf[4..], protocol).expect("decoding failed");
+
+                // Fields a protocol version cannot carry are lost, but what
+                // remains must encode to the same bytes.
+                let mut w = Writer::new();
+                encode(&decoded, &mut w, protocol);
+                assert_eq!(w.into_inner(), buf, "{} at protocol {}", message, protocol);
+                if protocol == 39 {
+                    assert_eq!(format!("{:?}", decoded), format!("{:?}", message));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_message_type() {
+        assert!(decode(65, &[], 39).is_err());
+        assert!(decode(96, &[], 39).is_err());
+    }
+
+    #[test]
+    fn compile_job_fields() {
+        let job = compile_job();
+        let old = round_trip(&job, 29);
+        assert_eq!((old.job_id, old.language, old.remote_flags.clone()), (42, Language::CPlusPlus, job.remote_flags.clone()));
+        assert_eq!(old.compiler_name, "");
+        assert_eq!(round_trip(&job, 31).compiler_name, "g++");
+
+        let v31 = round_trip(&job, 31);
+        assert_eq!((v31.input_file.as_str(), v31.working_directory.as_str()), ("", ""));
+        let v34 = round_trip(&job, 34);
+        assert_eq!((v34.input_file.as_str(), v34.working_directory.as_str()), ("main.cpp", "/src"));
+        assert_eq!((v34.output_file.as_str(), v34.dwarf_fission), ("", false));
+        assert_eq!(round_trip(&job, 35), job);
+    }
+
+    #[test]
+    fn get_cs_fields() {
+        let data = get_cs();
+        assert_eq!(round_trip(&data, 21).preferred_host, "");
+        assert_eq!(round_trip(&data, 22).preferred_host, "builder");
+        assert_eq!(round_trip(&data, 29).minimal_host_version, 0);
+        // Protocol 31 can only tell whether at least version 31 is needed.
+        assert_eq!(round_trip(&data, 31).minimal_host_version, 31);
+        assert_eq!(round_trip(&data, 34).minimal_host_version, 34);
+        assert_eq!(round_trip(&data, 35).required_features, 0);
+        assert_eq!(round_trip(&data, 39), data);
+    }
+
+    #[test]
+    fn use_cs_fields() {
+        let data = UseCSData { job_id: 1, matched_job_id: 2, .. Default::default() };
+        assert_eq!(round_trip(&data, 26).matched_job_id, 0);
+        assert_eq!(round_trip(&data, 28).matched_job_id, 2);
+    }
+
+    #[test]
+    fn login_fields() {
+        let data = login();
+        assert!(!round_trip(&data, 22).noremote);
+        assert!(round_trip(&data, 26).noremote);
+        assert_eq!(round_trip(&data, 35).supported_features, 0);
+        assert_eq!(round_trip(&data, 39), data);
+    }
+
+    #[test]
+    fn compile_result_fields() {
+        let data = CompileResultData { status: 1, have_dwo_file: true, .. Default::default() };
+        assert!(!round_trip(&data, 34).have_dwo_file);
+        assert_eq!(round_trip(&data, 35), data);
+    }
+
+    #[test]
+    fn job_begin_fields() {
+        let data = JobBeginData { job_id: 1, start_time: 2, client_count: 3 };
+        assert_eq!(round_trip(&data, 35).client_count, 0);
+        assert_eq!(round_trip(&data, 39), data);
+    }
+
+    // Before protocol 29 monitors were sent the whole GetCS request.
+    #[test]
+    fn monitor_get_cs_fields() {
+        let data = MonitorGetCSData {
+            filename: "main.c".to_owned(),
+            language: Language::C,
+            job_id: 1,
+            client_id: 2,
+            target: "x86_64".to_owned(),
+        };
+        assert_eq!(round_trip(&data, 28), data);
+        assert_eq!(round_trip(&data, 29), MonitorGetCSData { target: String::new(), .. data });
+    }
+
+    #[test]
+    fn file_chunk() {
+        for &len in &[0, 1, 100 * 1024] {
+            let data = FileChunkData { data: (0..len).map(|i| (i % 251) as u8).collect() };
+            assert_eq!(round_trip(&data, 39), data);
+        }
+    }
+
+    #[test]
+    fn oversized_chunk() {
+        let mut w = Writer::new();
+        w.compressed(&vec![0; MAX_MSG_SIZE + 1]);
+        let buf = w.into_inner();
+        assert!(Reader::new(&buf).compressed().is_err());
+    }
+
+    #[test]
+    fn truncated_messages() {
+        // Missing trailing fields read as defaults, as with libicecc.
+        let mut w = Writer::new();
+        w.u32(1);
+        let buf = w.into_inner();
+        let data = JobBeginData::decode(&mut Reader::new(&buf), 39).unwrap();
+        assert_eq!(data, JobBeginData { job_id: 1, .. Default::default() });
+
+        // Lists claiming more entries than there are must be rejected.
+        let mut w = Writer::new();
+        w.u32(1000);
+        let buf = w.into_inner();
+        assert!(Reader::new(&buf).strings().is_err());
+    }
+}
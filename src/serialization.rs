@@ -0,0 +1,148 @@
+//
+// serialization.rs
+// Copyright (C) 2017 Adrian Perez <aperez@igalia.com>
+// Distributed under terms of the MIT license.
+//
+
+use std::convert::TryFrom;
+use serde::{ de, ser, Deserialize, Deserializer, Serialize, Serializer };
+
+use super::{ CompileJob, data, msg };
+
+
+// Messages and jobs are (de)serialized through their plain data structs,
+// so the representation is the same for every backend.
+macro_rules! implement_serde {
+    ($($name:path => $data:ident)+) => {
+        $(
+            impl Serialize for $name {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    data::$data::try_from(self).map_err(ser::Error::custom)?.serialize(serializer)
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $name {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    let data = data::$data::deserialize(deserializer)?;
+                    <$name>::try_from(data).map_err(de::Error::custom)
+                }
+            }
+        )+
+    }
+}
+
+implement_serde! {
+    msg::Ping => PingData
+    msg::End => EndData
+    msg::GetNativeEnv => GetNativeEnvData
+    msg::NativeEnv => NativeEnvData
+    msg::GetCS => GetCSData
+    msg::UseCS => UseCSData
+    msg::CompileFile => CompileFileData
+    msg::FileChunk => FileChunkData
+    msg::CompileResult => CompileResultData
+    msg::JobBegin => JobBeginData
+    msg::JobDone => JobDoneData
+    msg::LocalJobBegin => LocalJobBeginData
+    msg::LocalJobDone => LocalJobDoneData
+    msg::Login => LoginData
+    msg::ConfCS => ConfCSData
+    msg::Stats => StatsData
+    msg::EnvTransfer => EnvTransferData
+    msg::InternalStatus => InternalStatusData
+    msg::MonitorLogin => MonitorLoginData
+    msg::MonitorGetCS => MonitorGetCSData
+    msg::MonitorJobBegin => MonitorJobBeginData
+    msg::MonitorJobDone => MonitorJobDoneData
+    msg::MonitorLocalJobBegin => MonitorLocalJobBeginData
+    msg::MonitorStats => MonitorStatsData
+    msg::Text => TextData
+    msg::StatusText => StatusTextData
+    msg::VerifyEnv => VerifyEnvData
+    msg::VerifyEnvResult => VerifyEnvResultData
+    msg::BlacklistHostEnv => BlacklistHostEnvData
+    CompileJob => CompileJobData
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use bincode;
+    use serde_json;
+
+    use { CompileJob, Language, msg };
+    use data::*;
+
+    // Serializes a message built from the data, and checks that what is
+    // deserialized converts back to the same data, in both formats.
+    macro_rules! assert_round_trip {
+        ($name:path, $data:expr) => {{
+            let data = $data;
+            let message = <$name>::try_from(data.clone()).unwrap();
+
+            let json = serde_json::to_string(&message).unwrap();
+            let decoded: $name = serde_json::from_str(&json).unwrap();
+            assert_eq!(TryFrom::try_from(&decoded).ok(), Some(data.clone()));
+
+            let bytes = bincode::serialize(&message).unwrap();
+            let decoded: $name = bincode::deserialize(&bytes).unwrap();
+            assert_eq!(TryFrom::try_from(&decoded).ok(), Some(data));
+        }}
+    }
+
+    fn compile_job() -> CompileJobData {
+        CompileJobData {
+            job_id: 42,
+            language: Language::CPlusPlus,
+            compiler_name: "g++".to_owned(),
+            environment_version: "env.tar.gz".to_owned(),
+            input_file: "main.cpp".to_owned(),
+            output_file: "main.o".to_owned(),
+            target_platform: "x86_64".to_owned(),
+            working_directory: "/src".to_owned(),
+            remote_flags: vec!["-O2".to_owned(), "-g".to_owned()],
+            rest_flags: vec!["-DNDEBUG".to_owned()],
+            dwarf_fission: true,
+        }
+    }
+
+    #[test]
+    fn string_fields() {
+        assert_round_trip!(msg::Text, TextData { text: "hello".to_owned() });
+    }
+
+    #[test]
+    fn string_list_fields() {
+        assert_round_trip!(msg::GetNativeEnv, GetNativeEnvData {
+            compiler: "gcc".to_owned(),
+            extra_files: vec!["a".to_owned(), "b".to_owned()],
+        });
+    }
+
+    #[test]
+    fn environment_fields() {
+        assert_round_trip!(msg::Login, LoginData {
+            port: 10245,
+            max_jobs: 8,
+            environments: vec![("x86_64".to_owned(), "env.tar.gz".to_owned())],
+            node_name: "builder".to_owned(),
+            host_platform: "x86_64".to_owned(),
+            chroot_possible: true,
+            noremote: true,
+            supported_features: 1,
+        });
+    }
+
+    #[test]
+    fn byte_fields() {
+        assert_round_trip!(msg::FileChunk, FileChunkData { data: vec![0, 1, 2, 255] });
+    }
+
+    #[test]
+    fn compile_job_fields() {
+        assert_round_trip!(CompileJob, compile_job());
+        assert_round_trip!(msg::CompileFile, CompileFileData { job: compile_job() });
+    }
+}
@@ -0,0 +1,160 @@
+//
+// status.rs
+// Copyright (C) 2017 Adrian Perez <aperez@igalia.com>
+// Distributed under terms of the MIT license.
+//
+
+#[cfg(feature = "serde")]
+use serde::{ Deserialize, Serialize };
+
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct InternalClient {
+    pub id: u32,
+    pub status: String,
+    pub job_id: Option<u32>,
+    // The whole line describing the client, as reported by the daemon.
+    pub description: String,
+}
+
+// Structured view of the text returned by iceccd when asked for its
+// internal status. The format is meant for humans and changes between
+// versions, so lines which are not understood are kept as they are.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct InternalStatus {
+    pub node_name: String,
+    pub current_jobs: u32,
+    pub max_jobs: u32,
+    // Installed environments, with the time they were last used.
+    pub environments: Vec<(String, u64)>,
+    // Native environments, as (compiler, tarball) pairs.
+    pub native_environments: Vec<(String, String)>,
+    pub clients: Vec<InternalClient>,
+    pub other: Vec<String>,
+}
+
+// Parses "<prefix><number><rest>", returning the number and the rest.
+fn leading_number<'a>(s: &'a str, prefix: &str) -> Option<(u32, &'a str)> {
+    let s = s.strip_prefix(prefix)?;
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    Some((s[..end].parse().ok()?, &s[end..]))
+}
+
+// Splits "<prefix><key><separator><value>" into the key and the value.
+fn split_pair<'a>(s: &'a str, prefix: &str, separator: &str) -> Option<(&'a str, &'a str)> {
+    let s = s.strip_prefix(prefix)?;
+    let pos = s.find(separator)?;
+    Some((&s[..pos], &s[pos + separator.len()..]))
+}
+
+fn parse_client(line: &str) -> Option<InternalClient> {
+    let (id, rest) = leading_number(line, "client ")?;
+    let description = rest.strip_prefix(':')?.trim();
+    let job_id = description.find("Job ID: ")
+        .and_then(|pos| leading_number(&description[pos..], "Job ID: "))
+        .map(|(job_id, _)| job_id);
+    Some(InternalClient {
+        id,
+        status: description.split_whitespace().next().unwrap_or("").to_owned(),
+        job_id,
+        description: description.to_owned(),
+    })
+}
+
+impl InternalStatus
+{
+    pub fn parse(text: &str) -> Self {
+        let mut status = Self::default();
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            if let Some(name) = line.strip_prefix("Node Name:") {
+                status.node_name = name.trim().to_owned();
+            } else if let Some((current, rest)) = leading_number(line, "Current kids: ") {
+                status.current_jobs = current;
+                status.max_jobs = leading_number(rest, " (max: ").map(|(max, _)| max).unwrap_or(0);
+            } else if let Some(env) = split_pair(line, "envs_last_use[", "] = ") {
+                status.environments.push((env.0.to_owned(), env.1.trim().parse().unwrap_or(0)));
+            } else if let Some(env) = split_pair(line, "NativeEnv (", "): ") {
+                status.native_environments.push((env.0.to_owned(), env.1.trim().to_owned()));
+            } else if let Some(client) = parse_client(line) {
+                status.clients.push(client);
+            } else {
+                status.other.push(line.to_owned());
+            }
+        }
+        status
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ InternalClient, InternalStatus };
+
+    // As dumped by iceccd 1.3 while compiling a job remotely.
+    const DUMP: &str = "\
+Node Name: builder
+  Remote name: scheduler.example.org
+  fd2chan[5] = 127.0.0.1:41834 (C eof: 0)
+  client 5: waitforcs 127.0.0.1:41834 (C eof: 0) ClientID: 5 Job ID: 12 CompileServer: worker1
+  client 6: gotnativeenv /var/run/icecc/iceccd.socket:0 (C eof: 0) ClientID: 6
+  Architecture: x86_64
+  NativeEnv (/usr/bin/gcc): /var/cache/icecc/native/1a2b3c.tar.gz
+  Now: 1500000100
+  envs_last_use[x86_64/1a2b3c.tar.gz] = 1500000000
+  Current kids: 1 (max: 8)
+  Scheduler protocol: 39
+  cpu: 950 idle, 0 nice
+  load: 0.12, icecream_load: 0
+  memory: 1024 (free: 2048)
+";
+
+    #[test]
+    fn parse() {
+        let status = InternalStatus::parse(DUMP);
+        assert_eq!(status.node_name, "builder");
+        assert_eq!((status.current_jobs, status.max_jobs), (1, 8));
+        assert_eq!(status.environments, [("x86_64/1a2b3c.tar.gz".to_owned(), 1500000000)]);
+        assert_eq!(status.native_environments,
+                   [("/usr/bin/gcc".to_owned(), "/var/cache/icecc/native/1a2b3c.tar.gz".to_owned())]);
+        assert_eq!(status.clients, [
+            InternalClient {
+                id: 5,
+                status: "waitforcs".to_owned(),
+                job_id: Some(12),
+                description: "waitforcs 127.0.0.1:41834 (C eof: 0) ClientID: 5 Job ID: 12 CompileServer: worker1".to_owned(),
+            },
+            InternalClient {
+                id: 6,
+                status: "gotnativeenv".to_owned(),
+                job_id: None,
+                description: "gotnativeenv /var/run/icecc/iceccd.socket:0 (C eof: 0) ClientID: 6".to_owned(),
+            },
+        ]);
+        assert_eq!(status.other, [
+            "Remote name: scheduler.example.org",
+            "fd2chan[5] = 127.0.0.1:41834 (C eof: 0)",
+            "Architecture: x86_64",
+            "Now: 1500000100",
+            "Scheduler protocol: 39",
+            "cpu: 950 idle, 0 nice",
+            "load: 0.12, icecream_load: 0",
+            "memory: 1024 (free: 2048)",
+        ]);
+    }
+
+    #[test]
+    fn parse_empty() {
+        assert_eq!(InternalStatus::parse(""), InternalStatus::default());
+        assert_eq!(InternalStatus::parse("\n  \n"), InternalStatus::default());
+    }
+
+    #[test]
+    fn parse_malformed() {
+        let status = InternalStatus::parse("Current kids: many\nclient x: waitforcs\n");
+        assert_eq!((status.current_jobs, status.max_jobs), (0, 0));
+        assert!(status.clients.is_empty());
+        assert_eq!(status.other, ["Current kids: many", "client x: waitforcs"]);
+    }
+}